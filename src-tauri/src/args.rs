@@ -0,0 +1,153 @@
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// The tedana options the UI exposes, mirroring `TedanaConfig` on the
+/// frontend. Empty strings are treated the same as unset options.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TedanaArgs {
+    pub data_files: Vec<String>,
+    pub echo_times: Vec<f64>,
+    pub out_dir: Option<String>,
+    pub mask: Option<String>,
+    pub prefix: Option<String>,
+    pub convention: Option<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub mask_type: Vec<String>,
+    pub fit_type: Option<String>,
+    pub comb_mode: Option<String>,
+    pub tedpca: Option<String>,
+    pub tree: Option<String>,
+    pub seed: Option<i64>,
+    pub maxit: Option<u32>,
+    pub maxrestart: Option<u32>,
+    pub tedort: bool,
+    pub gscontrol: Option<String>,
+    pub no_reports: bool,
+    pub png_cmap: Option<String>,
+    pub verbose: bool,
+    pub lowmem: bool,
+    pub n_threads: Option<i32>,
+    pub debug: bool,
+    pub t2smap: Option<String>,
+    pub mix: Option<String>,
+    pub overwrite: bool,
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().filter(|v| !v.trim().is_empty())
+}
+
+/// Also accepts a single string, which is how `maskType` was stored before
+/// it could hold several mask types.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(Option<String>),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => value.into_iter().collect(),
+        OneOrMany::Many(values) => values,
+    })
+}
+
+impl TedanaArgs {
+    /// Serialises the options to the argv tedana's CLI expects, one element
+    /// per argument, so paths are passed through untouched.
+    pub fn to_argv(&self) -> Vec<String> {
+        let mut argv = Vec::new();
+
+        if !self.data_files.is_empty() {
+            argv.push("-d".to_string());
+            argv.extend(self.data_files.iter().cloned());
+        }
+        if !self.echo_times.is_empty() {
+            argv.push("-e".to_string());
+            argv.extend(self.echo_times.iter().map(|t| t.to_string()));
+        }
+        let mask_types: Vec<&String> = self
+            .mask_type
+            .iter()
+            .filter(|v| !v.trim().is_empty())
+            .collect();
+        if !mask_types.is_empty() {
+            argv.push("--masktype".to_string());
+            argv.extend(mask_types.into_iter().cloned());
+        }
+
+        let options = [
+            ("--out-dir", non_empty(&self.out_dir)),
+            ("--mask", non_empty(&self.mask)),
+            ("--prefix", non_empty(&self.prefix)),
+            ("--convention", non_empty(&self.convention)),
+            ("--fittype", non_empty(&self.fit_type)),
+            ("--combmode", non_empty(&self.comb_mode)),
+            ("--tedpca", non_empty(&self.tedpca)),
+            ("--tree", non_empty(&self.tree)),
+            ("--gscontrol", non_empty(&self.gscontrol)),
+            ("--png-cmap", non_empty(&self.png_cmap)),
+            ("--t2smap", non_empty(&self.t2smap)),
+            ("--mix", non_empty(&self.mix)),
+        ];
+        for (flag, value) in options {
+            if let Some(value) = value {
+                argv.push(flag.to_string());
+                argv.push(value.to_string());
+            }
+        }
+
+        let numeric_options = [
+            ("--seed", self.seed),
+            ("--maxit", self.maxit.map(i64::from)),
+            ("--maxrestart", self.maxrestart.map(i64::from)),
+            ("--n-threads", self.n_threads.map(i64::from)),
+        ];
+        for (flag, value) in numeric_options {
+            if let Some(value) = value {
+                argv.push(flag.to_string());
+                argv.push(value.to_string());
+            }
+        }
+
+        let flags = [
+            ("--tedort", self.tedort),
+            ("--no-reports", self.no_reports),
+            ("--verbose", self.verbose),
+            ("--lowmem", self.lowmem),
+            ("--debug", self.debug),
+            ("--overwrite", self.overwrite),
+        ];
+        for (flag, enabled) in flags {
+            if enabled {
+                argv.push(flag.to_string());
+            }
+        }
+
+        argv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emits_every_mask_type() {
+        let args = TedanaArgs {
+            mask_type: vec!["dropout".to_string(), "decay".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(args.to_argv(), ["--masktype", "dropout", "decay"]);
+    }
+
+    #[test]
+    fn reads_a_single_mask_type_from_older_queues() {
+        let args: TedanaArgs = serde_json::from_str(r#"{"maskType": "decay"}"#).unwrap();
+        assert_eq!(args.mask_type, ["decay"]);
+        let args: TedanaArgs = serde_json::from_str(r#"{"maskType": null}"#).unwrap();
+        assert!(args.mask_type.is_empty());
+        let args: TedanaArgs = serde_json::from_str(r#"{"maskType": ["none"]}"#).unwrap();
+        assert_eq!(args.mask_type, ["none"]);
+    }
+}
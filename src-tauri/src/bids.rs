@@ -1,235 +1,596 @@
-use regex::Regex;
+use crate::bids_dataset::BidsIgnore;
+use crate::bids_entities::{self, BidsEntities};
+use crate::bids_error::BidsError;
+use crate::bids_index::BidsIndex;
+use crate::bids_tables::{self, Attributes, Table};
+use crate::nifti::NiftiHeader;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct EchoNum(u8);
 
+impl EchoNum {
+    /// The echo index in a BIDS filename, e.g. 10 for `..._echo-10_bold.nii.gz`.
+    pub fn from_path(path: &Path) -> Option<EchoNum> {
+        let echo = BidsEntities::from_path(path).ok()?.echo()?;
+        u8::try_from(echo).ok().map(EchoNum)
+    }
+
+    pub fn get(self) -> u8 {
+        self.0
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BoldMetadata {
     pub echo_num: EchoNum,
     pub delay_time: Option<f64>,
+    /// In seconds, converted from milliseconds when the sidecar used them.
     pub echo_time: Option<f64>,
     pub repetition_time: Option<f64>,
     pub skull_stripped: Option<bool>,
     pub slice_timing_corrected: Option<bool>,
     pub start_time: Option<f64>,
     pub task_name: Option<String>,
+    /// The sidecar each field above was read from, keyed by its BIDS name
+    /// (e.g. `EchoTime`).
+    pub sources: BTreeMap<String, String>,
+}
+
+/// Sidecar fields that `BoldMetadata` reads.
+const METADATA_FIELDS: &[&str] = &[
+    "DelayTime",
+    "EchoTime",
+    "RepetitionTime",
+    "SkullStripped",
+    "SliceTimingCorrected",
+    "StartTime",
+    "TaskName",
+];
+
+/// One functional acquisition within a session, e.g. `task-rest_run-1`, with
+/// the files of all its echoes.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FunctionalRun {
+    /// The run's identifying entities as they appear in the filenames.
+    pub key: String,
+    pub task: Option<String>,
+    pub acq: Option<String>,
+    pub ce: Option<String>,
+    pub rec: Option<String>,
+    pub dir: Option<String>,
+    pub run: Option<u32>,
+    /// Output space of preprocessed derivatives; `None` for raw data.
+    pub space: Option<String>,
+    pub echo_nifti_file_paths: Vec<String>,
+    /// Sidecars next to the echoes in `func/`. Metadata may also be inherited
+    /// from higher levels; see `BoldMetadata::sources`.
+    pub sidecar_file_paths: Vec<String>,
+    pub metadata: Vec<BoldMetadata>,
+    /// Header of each echo, in the order of `echo_nifti_file_paths`; `None`
+    /// when the file couldn't be read as NIfTI.
+    pub headers: Vec<Option<NiftiHeader>>,
+    /// Problems found while reading the run's metadata, such as echo times
+    /// that had to be converted to seconds.
+    pub warnings: Vec<String>,
+    /// Brain mask produced for this run by the derivatives pipeline.
+    pub mask: Option<String>,
+    /// Each echo's row of the session's `scans.tsv`, in the order of
+    /// `echo_nifti_file_paths`.
+    pub scans: Vec<Attributes>,
+}
+
+impl FunctionalRun {
+    fn new(entities: &BidsEntities) -> Self {
+        let label = |key: &str| entities.get(key).map(String::from);
+        FunctionalRun {
+            key: entities.run_key(),
+            task: label("task"),
+            acq: label("acq"),
+            ce: label("ce"),
+            rec: label("rec"),
+            dir: label("dir"),
+            run: entities.index("run"),
+            space: label("space"),
+            echo_nifti_file_paths: Vec::new(),
+            sidecar_file_paths: Vec::new(),
+            metadata: Vec::new(),
+            headers: Vec::new(),
+            warnings: Vec::new(),
+            mask: None,
+            scans: Vec::new(),
+        }
+    }
+
+    /// The sidecar echo time of each file in `echo_nifti_file_paths`, in
+    /// seconds.
+    pub fn echo_times(&self) -> Vec<Option<f64>> {
+        self.echo_nifti_file_paths
+            .iter()
+            .map(|file| {
+                let echo = EchoNum::from_path(Path::new(file))?;
+                self.metadata
+                    .iter()
+                    .find(|metadata| metadata.echo_num == echo)?
+                    .echo_time
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Session {
     pub sub_id: usize,
     pub name: String,
-    pub echo_nifti_file_paths: Vec<String>,
+    /// The session's row of the subject's `sessions.tsv`.
+    pub attributes: Attributes,
+    pub runs: Vec<FunctionalRun>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Subject {
     pub id: usize,
     pub name: String,
+    /// The subject's row of `participants.tsv`, e.g. group, age or site.
+    pub attributes: Attributes,
     pub sessions: Vec<Session>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct BidsStructure {
-    pub metadata: Vec<BoldMetadata>,
-    pub subjects: Vec<Subject>,
+/// Identifies a functional run within the dataset.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunRef {
+    pub subject: String,
+    pub session: String,
+    pub run: String,
 }
 
-pub fn validate_bids_directory(path: String, convention: String) -> Result<String, String> {
-    let dir_path = Path::new(&path);
-    if !dir_path.is_dir() {
-        return Err("The provided path is not a directory".to_string());
-    }
+/// An acquisition protocol shared by one or more runs. Echo times and the
+/// repetition time are in seconds, as stored in the sidecars.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Protocol {
+    pub echo_count: usize,
+    pub echo_times: Vec<Option<f64>>,
+    pub repetition_time: Option<f64>,
+    pub runs: Vec<RunRef>,
+}
 
-    // Create a regex to match "sub-" followed by any characters
-    let sub_regex = Regex::new(r"^sub-").unwrap();
+impl Protocol {
+    fn matches(&self, echo_count: usize, echo_times: &[Option<f64>], tr: Option<f64>) -> bool {
+        // Sidecars written by different converters round differently.
+        let close = |a: Option<f64>, b: Option<f64>| match (a, b) {
+            (Some(a), Some(b)) => (a - b).abs() < 1e-6,
+            (None, None) => true,
+            _ => false,
+        };
+        self.echo_count == echo_count
+            && self.echo_times.len() == echo_times.len()
+            && self
+                .echo_times
+                .iter()
+                .zip(echo_times)
+                .all(|(a, b)| close(*a, *b))
+            && close(self.repetition_time, tr)
+    }
+}
 
-    // Check for sub-* directories
-    let sub_dirs: Vec<_> = fs::read_dir(dir_path)
-        .map_err(|e| e.to_string())?
-        .filter_map(|entry| {
-            let entry = entry.ok()?;
-            let file_name = entry.file_name().to_string_lossy().into_owned();
-            if sub_regex.is_match(&file_name) && entry.file_type().ok()?.is_dir() {
-                Some(entry.path())
-            } else {
-                None
-            }
-        })
-        .collect();
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BidsStructure {
+    /// The distinct protocols found across all runs, most common first.
+    pub protocols: Vec<Protocol>,
+    pub subjects: Vec<Subject>,
+}
 
-    if sub_dirs.is_empty() {
-        return Err("No sub-* directories found".to_string());
-    }
+/// Selects preprocessed echoes from a derivatives pipeline, e.g.
+/// `derivatives/fmriprep`, instead of raw data.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DerivativeQuery {
+    /// Folder name under `derivatives/`, or the path of the pipeline folder.
+    pub pipeline: String,
+    /// Only outputs in this space, e.g. `MNI152NLin2009cAsym`. Every space
+    /// is included when unset, each as its own run.
+    #[serde(default)]
+    pub space: Option<String>,
+    /// The `desc` of the echoes to use; `preproc` when unset.
+    #[serde(default)]
+    pub desc: Option<String>,
+}
 
-    for sub_dir in sub_dirs {
-        validate_subject_directory(&sub_dir, &convention)?;
+impl DerivativeQuery {
+    /// The pipeline folder within the dataset at `dataset_root`.
+    pub fn pipeline_dir(&self, dataset_root: &Path) -> PathBuf {
+        if Path::new(&self.pipeline).is_absolute() {
+            PathBuf::from(&self.pipeline)
+        } else {
+            dataset_root.join("derivatives").join(&self.pipeline)
+        }
     }
+}
 
-    Ok("This directory is BIDS-compatible".to_string())
+/// Which files of a dataset are tedana inputs.
+enum Inputs<'a> {
+    /// Raw echoes matching a naming convention such as `bold`.
+    Convention(&'a str),
+    /// Preprocessed echoes from a derivatives pipeline.
+    Derivatives {
+        space: Option<&'a str>,
+        desc: &'a str,
+    },
 }
 
-fn validate_subject_directory(sub_dir: &Path, convention: &str) -> Result<(), String> {
-    // Create a regex to match "ses-" followed by any number (with or without leading zero)
-    let ses_regex = Regex::new(r"^ses-\d+$").unwrap();
+struct Scan<'a> {
+    /// The folder holding the `sub-*` directories.
+    root: PathBuf,
+    ignore: BidsIgnore,
+    /// For derivatives, the raw dataset whose sidecars they inherit from.
+    raw_root: Option<PathBuf>,
+    raw_ignore: BidsIgnore,
+    inputs: Inputs<'a>,
+    /// Parsed files from earlier scans, reused when they haven't changed.
+    index: RefCell<BidsIndex>,
+}
 
-    let ses_dirs: Vec<_> = fs::read_dir(sub_dir)
-        .map_err(|e| format!("Failed to read subject directory {:?}: {}", sub_dir, e))?
-        .filter_map(|entry| {
-            let entry = entry.ok()?;
-            let file_name = entry.file_name().to_string_lossy().into_owned();
-            if ses_regex.is_match(&file_name) && entry.file_type().ok()?.is_dir() {
-                Some(entry.path())
-            } else {
-                None
+impl Scan<'_> {
+    fn is_echo(&self, entities: &BidsEntities) -> bool {
+        if entities.echo().is_none() || !(entities.is_nifti() || entities.is_json()) {
+            return false;
+        }
+        match self.inputs {
+            Inputs::Convention(convention) => entities.matches_convention(convention),
+            Inputs::Derivatives { space, desc } => {
+                entities.suffix == "bold"
+                    && entities.get("desc") == Some(desc)
+                    && match space {
+                        Some(space) => entities.get("space") == Some(space),
+                        None => true,
+                    }
             }
-        })
-        .collect();
-
-    if ses_dirs.is_empty() {
-        validate_func_directory(sub_dir, convention)?;
-    } else {
-        for ses_dir in ses_dirs {
-            validate_func_directory(&ses_dir, convention)?;
         }
     }
 
-    Ok(())
+    fn is_brain_mask(&self, entities: &BidsEntities) -> bool {
+        matches!(self.inputs, Inputs::Derivatives { .. })
+            && entities.suffix == "mask"
+            && entities.get("desc") == Some("brain")
+            && entities.echo().is_none()
+            && entities.is_nifti()
+    }
 }
 
-fn validate_func_directory(dir: &Path, convention: &str) -> Result<(), String> {
-    let func_dir = dir.join("func");
-    if !func_dir.is_dir() {
-        return Err(format!("No 'func' directory found in {:?}", dir));
+impl<'a> Scan<'a> {
+    /// Scans the raw data of the dataset at `dir_path` for files matching
+    /// `convention`, or the outputs of a derivatives pipeline inside it.
+    fn new(
+        dir_path: &str,
+        convention: &'a str,
+        derivatives: Option<&'a DerivativeQuery>,
+    ) -> Result<Scan<'a>, BidsError> {
+        let dataset_root = Path::new(dir_path);
+        let Some(query) = derivatives else {
+            println!("Starting BIDS structure extraction from: {}", dir_path);
+            return Ok(Scan {
+                root: dataset_root.to_path_buf(),
+                ignore: BidsIgnore::load(dataset_root),
+                raw_root: None,
+                raw_ignore: BidsIgnore::default(),
+                inputs: Inputs::Convention(convention),
+                index: RefCell::new(BidsIndex::load(dataset_root)),
+            });
+        };
+
+        let pipeline_dir = query.pipeline_dir(dataset_root);
+        println!(
+            "Starting derivatives structure extraction from: {:?}",
+            pipeline_dir
+        );
+        if !pipeline_dir.is_dir() {
+            return Err(BidsError::missing(
+                &pipeline_dir,
+                "No derivatives pipeline folder found",
+            ));
+        }
+        Ok(Scan {
+            ignore: BidsIgnore::load(&pipeline_dir),
+            root: pipeline_dir,
+            raw_root: Some(dataset_root.to_path_buf()),
+            raw_ignore: BidsIgnore::load(dataset_root),
+            inputs: Inputs::Derivatives {
+                space: query.space.as_deref().filter(|space| !space.is_empty()),
+                desc: query
+                    .desc
+                    .as_deref()
+                    .filter(|desc| !desc.is_empty())
+                    .unwrap_or("preproc"),
+            },
+            // Shared with scans of the raw data, whose sidecars are read too.
+            index: RefCell::new(BidsIndex::load(dataset_root)),
+        })
     }
+}
 
-    let bold_regex = Regex::new(&format!(
-        r"_{}\.(json|nii|nii\.gz)$",
-        regex::escape(convention)
-    ))
-    .unwrap();
+pub fn extract_bids_structure(
+    dir_path: &str,
+    convention: &str,
+) -> Result<BidsStructure, BidsError> {
+    let scan = Scan::new(dir_path, convention, None)?;
+    let structure = extract_structure(&scan)?;
+    scan.index.into_inner().save();
+    Ok(structure)
+}
 
-    let bold_files: Vec<_> = fs::read_dir(&func_dir)
-        .map_err(|e| format!("Failed to read func directory {:?}: {}", func_dir, e))?
-        .filter_map(|entry| {
-            let entry = entry.ok()?;
-            let file_name = entry.file_name().to_string_lossy().into_owned();
-            if bold_regex.is_match(&file_name) {
-                Some(file_name)
-            } else {
-                None
-            }
-        })
-        .collect();
+/// Like `extract_bids_structure`, but for the preprocessed echoes of a
+/// derivatives pipeline inside the dataset at `dir_path`. Runs are paired
+/// with the pipeline's brain masks.
+pub fn extract_derivatives_structure(
+    dir_path: &str,
+    query: &DerivativeQuery,
+) -> Result<BidsStructure, BidsError> {
+    let scan = Scan::new(dir_path, "", Some(query))?;
+    let structure = extract_structure(&scan)?;
+    scan.index.into_inner().save();
+    Ok(structure)
+}
 
-    if bold_files.is_empty() {
-        return Err(format!(
-            "No files matching the convention '{}' found in {:?}",
-            convention, func_dir
-        ));
+/// Rereads the named subjects of a structure extracted from the same dataset
+/// with the same inputs. Subjects that are new are added and those that no
+/// longer exist are dropped; all others are left as they are.
+pub fn refresh_subjects(
+    structure: &mut BidsStructure,
+    dir_path: &str,
+    convention: &str,
+    derivatives: Option<&DerivativeQuery>,
+    subjects: &BTreeSet<String>,
+) -> Result<(), BidsError> {
+    let scan = Scan::new(dir_path, convention, derivatives)?;
+    let participants = load_participants(&scan);
+    structure
+        .subjects
+        .retain(|subject| !subjects.contains(&subject.name));
+    for subject_dir in list_subject_dirs(&scan)? {
+        if subjects.contains(&directory_name(&subject_dir)) {
+            structure
+                .subjects
+                .push(extract_subject(&scan, &participants, &subject_dir));
+        }
     }
+    structure.subjects.sort_by(|a, b| a.name.cmp(&b.name));
+    number_subjects(&mut structure.subjects);
+    structure.protocols = summarize_protocols(&structure.subjects);
+    scan.index.into_inner().save();
+    Ok(())
+}
+
+fn extract_structure(scan: &Scan) -> Result<BidsStructure, BidsError> {
+    let subject_dirs = list_subject_dirs(scan)?;
+    println!("Found {} subject directories", subject_dirs.len());
 
-    // Check if there's at least one .json file and one .nii or .nii.gz file
-    let has_json = bold_files
+    let participants = load_participants(scan);
+    let mut subjects: Vec<Subject> = subject_dirs
         .iter()
-        .any(|f| f.ends_with(&format!("_{}.json", convention)));
-    let has_nii = bold_files.iter().any(|f| {
-        f.ends_with(&format!("_{}.nii", convention))
-            || f.ends_with(&format!("_{}.nii.gz", convention))
-    });
+        .map(|subject_dir| extract_subject(scan, &participants, subject_dir))
+        .collect();
+    number_subjects(&mut subjects);
 
-    if !has_json || !has_nii {
-        return Err(format!(
-            "Missing required JSON or NIfTI files with convention '{}' in {:?}",
-            convention, func_dir
-        ));
-    }
+    let protocols = summarize_protocols(&subjects);
+    println!("Found {} distinct protocols", protocols.len());
 
-    Ok(())
+    Ok(BidsStructure {
+        protocols,
+        subjects,
+    })
 }
 
-pub fn extract_bids_structure(dir_path: &str, convention: &str) -> Result<BidsStructure, String> {
-    println!("Starting BIDS structure extraction from: {}", dir_path);
-    let path = Path::new(dir_path);
-    let mut structure = BidsStructure {
-        metadata: Vec::new(),
-        subjects: Vec::new(),
-    };
-
+/// The `sub-*` directories of the scanned dataset, sorted by name.
+fn list_subject_dirs(scan: &Scan) -> Result<Vec<PathBuf>, BidsError> {
+    let path = scan.root.as_path();
     if !path.is_dir() {
-        return Err(format!("Provided path is not a directory: {}", dir_path));
+        return Err(BidsError::not_a_directory(path));
     }
 
-    // Extract subjects
-    let subject_dirs: Vec<_> = fs::read_dir(path)
-        .map_err(|e| format!("Failed to read directory {}: {}", dir_path, e))?
+    let mut subject_dirs: Vec<PathBuf> = fs::read_dir(path)
+        .map_err(|e| BidsError::io(path, e))?
         .filter_map(|entry| {
             let entry = entry.ok()?;
             let file_name = entry.file_name().to_string_lossy().into_owned();
-            if file_name.starts_with("sub-") && entry.file_type().ok()?.is_dir() {
+            if bids_entities::parse_directory_label(&file_name, "sub").is_ok()
+                && entry.file_type().ok()?.is_dir()
+                && !scan.ignore.is_ignored(&entry.path())
+            {
                 Some(entry.path())
             } else {
                 None
             }
         })
         .collect();
+    subject_dirs.sort();
+    Ok(subject_dirs)
+}
 
-    println!("Found {} subject directories", subject_dirs.len());
+fn directory_name(dir: &Path) -> String {
+    dir.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
 
-    for (subject_id, subject_dir) in subject_dirs.iter().enumerate() {
-        let subject_name = subject_dir
-            .file_name()
-            .unwrap()
-            .to_string_lossy()
-            .into_owned();
-        let mut subject = Subject {
-            id: subject_id,
-            name: subject_name,
-            sessions: Vec::new(),
+/// Derivatives rarely copy the raw dataset's tables, so they are read from
+/// there.
+fn tables_root<'s>(scan: &'s Scan) -> &'s Path {
+    scan.raw_root.as_deref().unwrap_or(&scan.root)
+}
+
+fn load_participants(scan: &Scan) -> Table {
+    let tables_root = tables_root(scan);
+    load_table(
+        &tables_root.join("participants.tsv"),
+        "participant_id",
+        &tables_root.join("participants.json"),
+    )
+}
+
+/// Subject ids are their position in the structure, which changes when
+/// subjects are added or removed.
+fn number_subjects(subjects: &mut [Subject]) {
+    for (subject_id, subject) in subjects.iter_mut().enumerate() {
+        subject.id = subject_id;
+        for session in &mut subject.sessions {
+            session.sub_id = subject_id;
+        }
+    }
+}
+
+/// Reads one subject's sessions and runs. A broken subject or session is
+/// left out rather than failing the whole dataset; `validate_bids_directory`
+/// reports why.
+fn extract_subject(scan: &Scan, participants: &Table, subject_dir: &Path) -> Subject {
+    let tables_root = tables_root(scan);
+    let subject_name = directory_name(subject_dir);
+    let mut subject = Subject {
+        id: 0,
+        attributes: participants.get(&subject_name),
+        name: subject_name,
+        sessions: Vec::new(),
+    };
+    let subject_tables = tables_root.join(&subject.name);
+    let sessions_table = load_table(
+        &subject_tables.join(format!("{}_sessions.tsv", subject.name)),
+        "session_id",
+        &tables_root.join("sessions.json"),
+    );
+
+    let sessions = match extract_sessions(subject_dir, &scan.ignore) {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            println!("Skipping {}: {}", subject.name, e);
+            Vec::new()
+        }
+    };
+
+    for session_name in &sessions {
+        let session_dir = if session_name.is_empty() {
+            subject_dir.to_path_buf()
+        } else {
+            subject_dir.join(session_name)
+        };
+
+        let mut runs = match extract_functional_runs(scan, &session_dir) {
+            Ok(runs) => runs,
+            Err(e) => {
+                println!("Skipping runs of {:?}: {}", session_dir, e);
+                Vec::new()
+            }
         };
 
-        let sessions = extract_sessions(subject_dir)?;
+        let (scans_dir, scans_prefix) = if session_name.is_empty() {
+            (subject_tables.clone(), subject.name.clone())
+        } else {
+            (
+                subject_tables.join(session_name),
+                format!("{}_{}", subject.name, session_name),
+            )
+        };
+        let scans = load_table(
+            &scans_dir.join(format!("{}_scans.tsv", scans_prefix)),
+            "filename",
+            &tables_root.join("scans.json"),
+        );
+        for run in &mut runs {
+            run.scans = run
+                .echo_nifti_file_paths
+                .iter()
+                .map(|file| scans.get(&scans_filename(scan, Path::new(file))))
+                .collect();
+        }
 
-        for (session_id, session_name) in sessions.iter().enumerate() {
-            let session_dir = if session_name.is_empty() {
-                subject_dir.to_path_buf()
+        subject.sessions.push(Session {
+            sub_id: 0,
+            name: session_name.clone(),
+            attributes: if session_name.is_empty() {
+                Attributes::new()
             } else {
-                subject_dir.join(session_name)
-            };
+                sessions_table.get(session_name)
+            },
+            runs,
+        });
+    }
 
-            let echo_nifti_file_paths = extract_echo_nifti_file_paths(&session_dir, convention)?;
+    subject
+}
 
-            let session = Session {
-                sub_id: subject_id,
-                name: session_name.clone(),
-                echo_nifti_file_paths,
-            };
+/// Reads an optional tabular file, logging rather than failing on a broken
+/// one so the imaging data stays usable.
+fn load_table(tsv: &Path, key_column: &str, root_dictionary: &Path) -> Table {
+    bids_tables::read_table(tsv, key_column, root_dictionary).unwrap_or_else(|e| {
+        println!("Ignoring {:?}: {}", tsv, e);
+        Table::default()
+    })
+}
 
-            subject.sessions.push(session);
+/// How `scans.tsv` lists a data file: its path relative to the session
+/// directory. Derivatives are looked up under the raw file they came from.
+fn scans_filename(scan: &Scan, data_file: &Path) -> String {
+    let file_name = match BidsEntities::from_path(data_file) {
+        Ok(entities) if scan.raw_root.is_some() => {
+            entities.without_derivative_entities().file_name()
+        }
+        _ => data_file
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+    };
+    format!("func/{}", file_name)
+}
 
-            // Extract metadata from the first subject's first session
-            if structure.metadata.is_empty() && session_id == 0 {
-                structure.metadata = extract_bold_metadata(&session_dir, convention)?;
+/// Groups runs by echo count, echo times and repetition time.
+fn summarize_protocols(subjects: &[Subject]) -> Vec<Protocol> {
+    let mut protocols: Vec<Protocol> = Vec::new();
+    for subject in subjects {
+        for session in &subject.sessions {
+            for run in &session.runs {
+                let echo_count = run.echo_nifti_file_paths.len();
+                let echo_times: Vec<Option<f64>> =
+                    run.metadata.iter().map(|m| m.echo_time).collect();
+                let repetition_time = run.metadata.iter().find_map(|m| m.repetition_time);
+                let run_ref = RunRef {
+                    subject: subject.name.clone(),
+                    session: session.name.clone(),
+                    run: run.key.clone(),
+                };
+
+                match protocols
+                    .iter_mut()
+                    .find(|p| p.matches(echo_count, &echo_times, repetition_time))
+                {
+                    Some(protocol) => protocol.runs.push(run_ref),
+                    None => protocols.push(Protocol {
+                        echo_count,
+                        echo_times,
+                        repetition_time,
+                        runs: vec![run_ref],
+                    }),
+                }
             }
         }
-
-        structure.subjects.push(subject);
     }
-
-    Ok(structure)
+    protocols.sort_by_key(|protocol| std::cmp::Reverse(protocol.runs.len()));
+    protocols
 }
 
-fn extract_sessions(subject_dir: &Path) -> Result<Vec<String>, String> {
+fn extract_sessions(subject_dir: &Path, ignore: &BidsIgnore) -> Result<Vec<String>, BidsError> {
     let mut session_dirs: Vec<_> = fs::read_dir(subject_dir)
-        .map_err(|e| format!("Failed to read subject directory {:?}: {}", subject_dir, e))?
+        .map_err(|e| BidsError::io(subject_dir, e))?
         .filter_map(|entry| {
             let entry = entry.ok()?;
             let file_name = entry.file_name().to_string_lossy().into_owned();
-            if file_name.starts_with("ses-") && entry.file_type().ok()?.is_dir() {
+            if bids_entities::parse_directory_label(&file_name, "ses").is_ok()
+                && entry.file_type().ok()?.is_dir()
+                && !ignore.is_ignored(&entry.path())
+            {
                 Some(file_name)
             } else {
                 None
@@ -246,71 +607,88 @@ fn extract_sessions(subject_dir: &Path) -> Result<Vec<String>, String> {
     }
 }
 
-fn extract_echo_nifti_file_paths(
+/// Groups a session's multi-echo files into runs by their task, acquisition,
+/// contrast, reconstruction, direction, run and (for derivatives) space
+/// entities.
+fn extract_functional_runs(
+    scan: &Scan,
     session_dir: &Path,
-    convention: &str,
-) -> Result<Vec<String>, String> {
+) -> Result<Vec<FunctionalRun>, BidsError> {
     let func_dir = session_dir.join("func");
-    let nifti_re = Regex::new(&format!(
-        r"_echo-[1-5].*{}.*\.(nii|nii\.gz)$",
-        regex::escape(convention)
-    ))
-    .unwrap();
-
-    let mut nifti_files = Vec::new();
-
-    for entry in fs::read_dir(&func_dir)
-        .map_err(|e| format!("Failed to read func directory {:?}: {}", func_dir, e))?
-    {
-        let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
-        let file_path = entry.path();
-        let file_name = file_path.file_name().unwrap().to_string_lossy();
+    let mut runs: Vec<FunctionalRun> = Vec::new();
+    let mut masks: Vec<(String, String)> = Vec::new();
 
-        if nifti_re.is_match(&file_name) {
-            nifti_files.push(file_path.to_string_lossy().into_owned());
+    for (file_path, entities) in list_func_files(&func_dir, &scan.ignore)? {
+        if scan.is_brain_mask(&entities) {
+            masks.push((entities.run_key(), file_path.to_string_lossy().into_owned()));
+            continue;
+        }
+        if !scan.is_echo(&entities) {
+            continue;
         }
-    }
-
-    nifti_files.sort();
-    Ok(nifti_files)
-}
 
-fn extract_bold_metadata(
-    session_dir: &Path,
-    convention: &str,
-) -> Result<Vec<BoldMetadata>, String> {
-    println!("Extracting BOLD metadata from: {:?}", session_dir);
-    let func_dir = session_dir.join("func");
-    if !func_dir.is_dir() {
-        return Err(format!("No 'func' directory found in {:?}", session_dir));
+        let key = entities.run_key();
+        let index = match runs.iter().position(|run| run.key == key) {
+            Some(index) => index,
+            None => {
+                runs.push(FunctionalRun::new(&entities));
+                runs.len() - 1
+            }
+        };
+        let file_path = file_path.to_string_lossy().into_owned();
+        if entities.is_nifti() {
+            runs[index].echo_nifti_file_paths.push(file_path);
+        } else {
+            runs[index].sidecar_file_paths.push(file_path);
+        }
     }
 
-    let json_re = Regex::new(&format!(
-        r"_echo-[1-5].*{}.*\.json$",
-        regex::escape(convention)
-    ))
-    .unwrap();
-
-    let mut json_files = Vec::new();
-
-    for entry in fs::read_dir(&func_dir)
-        .map_err(|e| format!("Failed to read func directory {:?}: {}", func_dir, e))?
-    {
-        let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
-        let file_path = entry.path();
-        let file_name = file_path.file_name().unwrap().to_string_lossy();
-
-        if json_re.is_match(&file_name) {
-            json_files.push(file_path);
-        }
+    // Runs without any image data have nothing for tedana to process.
+    runs.retain(|run| !run.echo_nifti_file_paths.is_empty());
+    for run in &mut runs {
+        // Numeric echo order, so echo-10 comes after echo-2.
+        run.echo_nifti_file_paths
+            .sort_by_key(|path| EchoNum::from_path(Path::new(path)));
+        run.sidecar_file_paths
+            .sort_by_key(|path| EchoNum::from_path(Path::new(path)));
+        run.metadata = extract_bold_metadata(scan, &run.echo_nifti_file_paths);
+        run.warnings = normalize_echo_times(&mut run.metadata);
+        run.headers = run
+            .echo_nifti_file_paths
+            .iter()
+            .map(
+                |path| match scan.index.borrow_mut().header(Path::new(path)) {
+                    Ok(header) => Some(header),
+                    Err(e) => {
+                        println!("Could not read NIfTI header: {}", e);
+                        None
+                    }
+                },
+            )
+            .collect();
+        run.mask = masks
+            .iter()
+            .find(|(key, _)| *key == run.key)
+            .map(|(_, path)| path.clone());
     }
+    runs.sort_by(|a, b| {
+        (&a.task, &a.acq, &a.ce, &a.rec, &a.dir, a.run, &a.space)
+            .cmp(&(&b.task, &b.acq, &b.ce, &b.rec, &b.dir, b.run, &b.space))
+    });
+    Ok(runs)
+}
 
-    println!("Found {} JSON files in func directory", json_files.len());
+fn extract_bold_metadata(scan: &Scan, echo_nifti_file_paths: &[String]) -> Vec<BoldMetadata> {
+    println!(
+        "Extracting BOLD metadata for {} echoes",
+        echo_nifti_file_paths.len()
+    );
 
     let mut metadata_vec = Vec::new();
-    for json_path in json_files {
-        if let Ok(metadata) = extract_file_metadata(&json_path) {
-            metadata_vec.push(metadata);
+    for nifti_path in echo_nifti_file_paths {
+        match extract_file_metadata(scan, Path::new(nifti_path)) {
+            Ok(metadata) => metadata_vec.push(metadata),
+            Err(e) => println!("Skipping metadata for {}: {}", nifti_path, e),
         }
     }
 
@@ -319,25 +697,110 @@ fn extract_bold_metadata(
     metadata_vec.dedup_by_key(|m| m.echo_num);
     println!("After deduplication: {} unique entries", metadata_vec.len());
 
-    Ok(metadata_vec)
+    metadata_vec
 }
 
-fn extract_file_metadata(file_path: &PathBuf) -> Result<BoldMetadata, String> {
-    let file_contents =
-        fs::read_to_string(file_path).map_err(|e| format!("Failed to read file: {}", e))?;
+/// Echo times outside this range, in seconds, are unlikely for multi-echo BOLD.
+const PLAUSIBLE_ECHO_TIMES: (f64, f64) = (0.001, 0.15);
+
+/// Converts echo times written in milliseconds to the seconds BIDS requires,
+/// and flags values that are implausible either way. Anything above one is
+/// taken as milliseconds, since no BOLD echo is a second long.
+fn normalize_echo_times(metadata: &mut [BoldMetadata]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut in_milliseconds = Vec::new();
+    let mut in_seconds = Vec::new();
+    for echo in metadata.iter_mut() {
+        let Some(echo_time) = echo.echo_time else {
+            continue;
+        };
+        let echo_num = echo.echo_num.get();
+
+        let seconds = if echo_time > 1.0 {
+            warnings.push(format!(
+                "Echo {} has an EchoTime of {}, which looks like milliseconds; using {} s",
+                echo_num,
+                echo_time,
+                echo_time / 1000.0
+            ));
+            in_milliseconds.push(echo_num);
+            echo_time / 1000.0
+        } else {
+            in_seconds.push(echo_num);
+            echo_time
+        };
 
-    let json: Value =
-        serde_json::from_str(&file_contents).map_err(|e| format!("Failed to parse JSON: {}", e))?;
+        let (low, high) = PLAUSIBLE_ECHO_TIMES;
+        if !(low..=high).contains(&seconds) {
+            warnings.push(format!(
+                "Echo {} has an implausible EchoTime of {} s for multi-echo BOLD",
+                echo_num, seconds
+            ));
+        }
+        echo.echo_time = Some(seconds);
+    }
 
-    let re = Regex::new(r"echo-(\d+)").unwrap();
-    let echo_num = re
-        .captures(&file_path.to_string_lossy())
-        .and_then(|cap| cap.get(1))
-        .and_then(|m| m.as_str().parse::<u8>().ok())
-        .ok_or_else(|| "Failed to extract echo number".to_string())?;
+    // Usually a sidecar for some echoes was regenerated by another converter,
+    // so the others may be wrong in ways the conversion can't fix.
+    if !in_milliseconds.is_empty() && !in_seconds.is_empty() {
+        warnings.push(format!(
+            "EchoTime is in milliseconds for echoes {:?} but in seconds for echoes {:?}; check that the sidecars belong to the same acquisition",
+            in_milliseconds, in_seconds
+        ));
+    }
+
+    warnings
+}
+
+/// Reads the metadata of one echo by merging every sidecar that applies to
+/// it, from the dataset root down to `func/`, with deeper files overriding
+/// shallower ones. Derivatives first inherit from the raw file they were
+/// made from, since pipelines rarely copy acquisition parameters.
+fn extract_file_metadata(scan: &Scan, data_file: &Path) -> Result<BoldMetadata, BidsError> {
+    let entities = BidsEntities::from_path(data_file)
+        .map_err(|reason| BidsError::invalid_name(data_file, reason))?;
+    let echo_num = EchoNum::from_path(data_file)
+        .ok_or_else(|| BidsError::invalid_name(data_file, "Failed to extract echo number"))?;
+
+    let mut sidecars = Vec::new();
+    if let (Some(raw_root), Ok(relative)) = (&scan.raw_root, data_file.strip_prefix(&scan.root)) {
+        let raw_file = raw_root.join(relative);
+        sidecars.extend(applicable_sidecars(
+            raw_root,
+            &scan.raw_ignore,
+            &raw_file,
+            &entities.without_derivative_entities(),
+            &mut scan.index.borrow_mut(),
+        ));
+    }
+    sidecars.extend(applicable_sidecars(
+        &scan.root,
+        &scan.ignore,
+        data_file,
+        &entities,
+        &mut scan.index.borrow_mut(),
+    ));
+    if sidecars.is_empty() {
+        return Err(BidsError::missing(
+            data_file,
+            "No sidecar applies to this file",
+        ));
+    }
+
+    let mut merged = Map::new();
+    let mut sources = BTreeMap::new();
+    for sidecar in sidecars {
+        for (key, value) in scan.index.borrow_mut().sidecar(&sidecar)? {
+            if METADATA_FIELDS.contains(&key.as_str()) {
+                sources.insert(key.clone(), sidecar.to_string_lossy().into_owned());
+            }
+            merged.insert(key, value);
+        }
+    }
+    let json = Value::Object(merged);
 
     Ok(BoldMetadata {
-        echo_num: EchoNum(echo_num),
+        echo_num,
         delay_time: json["DelayTime"].as_f64(),
         echo_time: json["EchoTime"].as_f64(),
         repetition_time: json["RepetitionTime"].as_f64(),
@@ -345,9 +808,88 @@ fn extract_file_metadata(file_path: &PathBuf) -> Result<BoldMetadata, String> {
         slice_timing_corrected: json["SliceTimingCorrected"].as_bool(),
         start_time: json["StartTime"].as_f64(),
         task_name: json["TaskName"].as_str().map(String::from),
+        sources,
     })
 }
 
+/// Reads the fields of a JSON sidecar.
+pub fn read_sidecar(sidecar: &Path) -> Result<Map<String, Value>, BidsError> {
+    let file_contents = fs::read_to_string(sidecar).map_err(|e| BidsError::io(sidecar, e))?;
+    match serde_json::from_str(&file_contents).map_err(|e| BidsError::json(sidecar, e))? {
+        Value::Object(fields) => Ok(fields),
+        _ => Err(BidsError::json_shape(
+            sidecar,
+            "The sidecar does not contain a JSON object",
+        )),
+    }
+}
+
+/// Sidecars that apply to `data_file`, in inheritance order: the dataset
+/// root first, then the subject, session and datatype directories. Within a
+/// level, files naming fewer entities come first so more specific ones win.
+/// Sidecars matched by `.bidsignore` are left out. Directory listings come
+/// from `index`, since every echo of every run looks at the same levels.
+pub fn applicable_sidecars(
+    dataset_root: &Path,
+    ignore: &BidsIgnore,
+    data_file: &Path,
+    entities: &BidsEntities,
+    index: &mut BidsIndex,
+) -> Vec<PathBuf> {
+    let mut levels: Vec<&Path> = data_file
+        .parent()
+        .into_iter()
+        .flat_map(Path::ancestors)
+        .take_while(|dir| dir.starts_with(dataset_root))
+        .collect();
+    levels.reverse();
+
+    let mut sidecars = Vec::new();
+    for level in levels {
+        let Ok(names) = index.listing(level) else {
+            continue;
+        };
+        let mut found: Vec<(usize, PathBuf)> = names
+            .iter()
+            .filter(|name| name.ends_with(".json"))
+            .filter_map(|name| {
+                let path = level.join(name);
+                if ignore.is_ignored(&path) {
+                    return None;
+                }
+                let sidecar = BidsEntities::parse(name).ok()?;
+                (sidecar.is_json() && sidecar.applies_to(entities))
+                    .then_some((sidecar.entities.len(), path))
+            })
+            .collect();
+        found.sort();
+        sidecars.extend(found.into_iter().map(|(_, path)| path));
+    }
+    sidecars
+}
+
+/// Parses every file in a `func` directory, skipping hidden and ignored files
+/// and logging names that aren't valid BIDS.
+fn list_func_files(
+    func_dir: &Path,
+    ignore: &BidsIgnore,
+) -> Result<Vec<(PathBuf, BidsEntities)>, BidsError> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(func_dir).map_err(|e| BidsError::io(func_dir, e))? {
+        let entry = entry.map_err(|e| BidsError::io(func_dir, e))?;
+        let file_path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if file_name.starts_with('.') || ignore.is_ignored(&file_path) {
+            continue;
+        }
+        match BidsEntities::parse(&file_name) {
+            Ok(entities) => files.push((file_path, entities)),
+            Err(reason) => println!("Skipping invalid BIDS filename: {}", reason),
+        }
+    }
+    Ok(files)
+}
+
 // fn find_matching_nifti<'a>(json_path: &Path, nifti_files: &'a [PathBuf]) -> Option<&'a PathBuf> {
 //     let json_stem = json_path.file_stem()?.to_str()?;
 
@@ -362,3 +904,78 @@ fn extract_file_metadata(file_path: &PathBuf) -> Result<BoldMetadata, String> {
 
 //     None
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(echo_times: &[Option<f64>]) -> Vec<BoldMetadata> {
+        echo_times
+            .iter()
+            .enumerate()
+            .map(|(index, echo_time)| BoldMetadata {
+                echo_num: EchoNum(index as u8 + 1),
+                delay_time: None,
+                echo_time: *echo_time,
+                repetition_time: None,
+                skull_stripped: None,
+                slice_timing_corrected: None,
+                start_time: None,
+                task_name: None,
+                sources: BTreeMap::new(),
+            })
+            .collect()
+    }
+
+    fn echo_times(metadata: &[BoldMetadata]) -> Vec<Option<f64>> {
+        metadata.iter().map(|echo| echo.echo_time).collect()
+    }
+
+    #[test]
+    fn keeps_seconds() {
+        let mut run = metadata(&[Some(0.014), Some(0.039), Some(0.064)]);
+        assert!(normalize_echo_times(&mut run).is_empty());
+        assert_eq!(echo_times(&run), [Some(0.014), Some(0.039), Some(0.064)]);
+    }
+
+    #[test]
+    fn converts_milliseconds() {
+        let mut run = metadata(&[Some(14.0), Some(39.0), Some(64.0)]);
+        let warnings = normalize_echo_times(&mut run);
+        assert_eq!(echo_times(&run), [Some(0.014), Some(0.039), Some(0.064)]);
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings
+            .iter()
+            .all(|w| w.contains("looks like milliseconds")));
+    }
+
+    #[test]
+    fn warns_when_a_run_mixes_units() {
+        let mut run = metadata(&[Some(0.014), Some(39.0), Some(64.0)]);
+        let warnings = normalize_echo_times(&mut run);
+        assert_eq!(echo_times(&run), [Some(0.014), Some(0.039), Some(0.064)]);
+        assert_eq!(warnings.len(), 3);
+        assert!(
+            warnings[2].contains("milliseconds for echoes [2, 3] but in seconds for echoes [1]")
+        );
+    }
+
+    #[test]
+    fn leaves_missing_values_alone() {
+        let mut run = metadata(&[Some(14.0), None, Some(64.0)]);
+        let warnings = normalize_echo_times(&mut run);
+        assert_eq!(echo_times(&run), [Some(0.014), None, Some(0.064)]);
+        assert_eq!(warnings.len(), 2);
+
+        let mut run = metadata(&[None, None]);
+        assert!(normalize_echo_times(&mut run).is_empty());
+    }
+
+    #[test]
+    fn flags_implausible_values() {
+        let mut run = metadata(&[Some(0.0005), Some(0.5)]);
+        let warnings = normalize_echo_times(&mut run);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings.iter().all(|w| w.contains("implausible")));
+    }
+}
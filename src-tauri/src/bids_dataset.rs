@@ -0,0 +1,165 @@
+use crate::bids_error::BidsError;
+use globset::{GlobBuilder, GlobMatcher};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Whether a dataset holds raw data or the outputs of a pipeline.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DatasetType {
+    #[default]
+    Raw,
+    Derivative,
+}
+
+/// A pipeline that produced (part of) a derivative dataset.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GeneratedBy {
+    #[serde(alias = "Name")]
+    pub name: String,
+    #[serde(alias = "Version", default)]
+    pub version: Option<String>,
+}
+
+/// The fields of `dataset_description.json` that affect how a dataset is
+/// read.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DatasetDescription {
+    #[serde(alias = "Name", default)]
+    pub name: Option<String>,
+    #[serde(alias = "BIDSVersion", default)]
+    pub bids_version: Option<String>,
+    /// `raw` when the file doesn't say.
+    #[serde(alias = "DatasetType", default)]
+    pub dataset_type: DatasetType,
+    #[serde(alias = "GeneratedBy", default)]
+    pub generated_by: Vec<GeneratedBy>,
+    /// The pre-1.4 spelling of `GeneratedBy`, merged into it when read.
+    #[serde(alias = "PipelineDescription", default, skip_serializing)]
+    pipeline_description: Option<GeneratedBy>,
+}
+
+/// Reads `dataset_description.json` at `root`. `Ok(None)` when there is none.
+pub fn read_description(root: &Path) -> Result<Option<DatasetDescription>, BidsError> {
+    let path = root.join("dataset_description.json");
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&path).map_err(|e| BidsError::io(&path, e))?;
+    let mut description: DatasetDescription =
+        serde_json::from_str(&content).map_err(|e| BidsError::json(&path, e))?;
+    if description.generated_by.is_empty() {
+        description
+            .generated_by
+            .extend(description.pipeline_description.take());
+    }
+    Ok(Some(description))
+}
+
+/// A pipeline folder under `derivatives/`.
+#[derive(Debug, Serialize, Clone)]
+pub struct DerivativeDataset {
+    /// The folder name, which `DerivativeQuery::pipeline` accepts.
+    pub name: String,
+    pub path: String,
+    pub description: Option<DatasetDescription>,
+}
+
+/// Lists the pipeline folders in the dataset's `derivatives/` directory.
+pub fn discover_derivatives(root: &Path, ignore: &BidsIgnore) -> Vec<DerivativeDataset> {
+    let Ok(entries) = fs::read_dir(root.join("derivatives")) else {
+        return Vec::new();
+    };
+    let mut derivatives: Vec<DerivativeDataset> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') || !entry.file_type().ok()?.is_dir() {
+                return None;
+            }
+            let path = entry.path();
+            if ignore.is_ignored(&path) {
+                return None;
+            }
+            Some(DerivativeDataset {
+                name,
+                path: path.to_string_lossy().into_owned(),
+                description: read_description(&path).ok().flatten(),
+            })
+        })
+        .collect();
+    derivatives.sort_by(|a, b| a.name.cmp(&b.name));
+    derivatives
+}
+
+/// The rules of a dataset's `.bidsignore`, which uses `.gitignore` syntax:
+/// patterns without a slash match at any depth, a leading slash anchors to
+/// the dataset root, and `!` re-includes a path. Matching a directory
+/// ignores everything inside it.
+#[derive(Debug, Default, Clone)]
+pub struct BidsIgnore {
+    root: PathBuf,
+    patterns: Vec<String>,
+    rules: Vec<(GlobMatcher, bool)>,
+}
+
+impl BidsIgnore {
+    /// Loads `.bidsignore` from `root`. Without one, nothing is ignored.
+    pub fn load(root: &Path) -> BidsIgnore {
+        let mut ignore = BidsIgnore {
+            root: root.to_path_buf(),
+            ..BidsIgnore::default()
+        };
+        let Ok(content) = fs::read_to_string(root.join(".bidsignore")) else {
+            return ignore;
+        };
+
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, pattern) = match line.strip_prefix('!') {
+                Some(pattern) => (true, pattern),
+                None => (false, line),
+            };
+            let pattern = pattern.trim_end_matches('/');
+            let glob = match pattern.strip_prefix('/') {
+                Some(anchored) => anchored.to_string(),
+                None if pattern.contains('/') => pattern.to_string(),
+                None => format!("**/{}", pattern),
+            };
+            let matchers = [glob.clone(), format!("{}/**", glob)].map(|glob| {
+                GlobBuilder::new(&glob)
+                    .literal_separator(true)
+                    .build()
+                    .map(|glob| glob.compile_matcher())
+            });
+            match matchers {
+                [Ok(path), Ok(contents)] => {
+                    ignore.rules.push((path, negated));
+                    ignore.rules.push((contents, negated));
+                    ignore.patterns.push(line.to_string());
+                }
+                _ => println!("Ignoring invalid .bidsignore pattern: {}", line),
+            }
+        }
+        ignore
+    }
+
+    /// The patterns that were read, as written in the file.
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    /// Whether `path`, absolute or relative to the dataset root, is ignored.
+    /// The last matching rule wins, as in `.gitignore`.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        self.rules
+            .iter()
+            .rev()
+            .find(|(matcher, _)| matcher.is_match(relative))
+            .is_some_and(|(_, negated)| !negated)
+    }
+}
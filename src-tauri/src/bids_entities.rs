@@ -0,0 +1,353 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// BIDS entities in the order the specification requires them to appear in a
+/// filename.
+pub const ENTITY_ORDER: &[&str] = &[
+    "sub",
+    "ses",
+    "sample",
+    "task",
+    "tracksys",
+    "acq",
+    "nuc",
+    "voi",
+    "ce",
+    "trc",
+    "stain",
+    "rec",
+    "dir",
+    "run",
+    "mod",
+    "echo",
+    "flip",
+    "inv",
+    "mt",
+    "part",
+    "proc",
+    "hemi",
+    "space",
+    "split",
+    "recording",
+    "chunk",
+    "seg",
+    "res",
+    "den",
+    "label",
+    "desc",
+];
+
+/// Entities whose value is a non-negative integer index rather than a label.
+const INDEX_ENTITIES: &[&str] = &["run", "echo", "flip", "inv", "split", "chunk"];
+
+/// Entities that tell one functional acquisition apart from another within a
+/// session. Files that agree on all of them are echoes of the same run. For
+/// derivatives, outputs in different spaces or resolutions are separate runs.
+pub const RUN_ENTITIES: &[&str] = &["task", "acq", "ce", "rec", "dir", "run", "space", "res"];
+
+/// Entities that derivatives add to the raw filename they were made from.
+pub const DERIVATIVE_ENTITIES: &[&str] = &["space", "res", "den", "desc"];
+
+/// A BIDS filename split into its entities, suffix and extension, e.g.
+/// `sub-01_task-rest_run-1_echo-2_bold.nii.gz`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BidsEntities {
+    /// Key/value pairs in filename order.
+    pub entities: Vec<(String, String)>,
+    pub suffix: String,
+    pub extension: String,
+}
+
+impl BidsEntities {
+    /// Parses a filename (not a path) and rejects anything the specification
+    /// doesn't allow, with the reason in the error.
+    pub fn parse(file_name: &str) -> Result<BidsEntities, String> {
+        let (stem, extension) = match file_name.find('.') {
+            Some(index) => (&file_name[..index], &file_name[index..]),
+            None => (file_name, ""),
+        };
+
+        let mut parts: Vec<&str> = stem.split('_').collect();
+        let suffix = parts
+            .pop()
+            .filter(|suffix| !suffix.is_empty() && !suffix.contains('-'))
+            .ok_or_else(|| format!("{}: missing suffix after the last entity", file_name))?;
+        if !suffix.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(format!(
+                "{}: suffix '{}' must be alphanumeric",
+                file_name, suffix
+            ));
+        }
+
+        let mut entities: Vec<(String, String)> = Vec::new();
+        let mut last_position = None;
+        for part in parts {
+            let (key, value) = part
+                .split_once('-')
+                .ok_or_else(|| format!("{}: '{}' is not a key-value entity", file_name, part))?;
+            let position = ENTITY_ORDER
+                .iter()
+                .position(|entity| *entity == key)
+                .ok_or_else(|| format!("{}: unknown entity '{}'", file_name, key))?;
+            if let Some(last) = last_position {
+                if position == last {
+                    return Err(format!("{}: entity '{}' appears twice", file_name, key));
+                }
+                if position < last {
+                    return Err(format!(
+                        "{}: entity '{}' must come before '{}'",
+                        file_name, key, ENTITY_ORDER[last]
+                    ));
+                }
+            }
+            if value.is_empty() || !value.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(format!(
+                    "{}: value of '{}' must be a non-empty alphanumeric label",
+                    file_name, key
+                ));
+            }
+            if INDEX_ENTITIES.contains(&key) && value.parse::<u32>().is_err() {
+                return Err(format!(
+                    "{}: value of '{}' must be a non-negative integer",
+                    file_name, key
+                ));
+            }
+            last_position = Some(position);
+            entities.push((key.to_string(), value.to_string()));
+        }
+
+        Ok(BidsEntities {
+            entities,
+            suffix: suffix.to_string(),
+            extension: extension.to_string(),
+        })
+    }
+
+    pub fn from_path(path: &Path) -> Result<BidsEntities, String> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| format!("{:?} has no file name", path))?
+            .to_string_lossy();
+        BidsEntities::parse(&file_name)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entities
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn index(&self, key: &str) -> Option<u32> {
+        self.get(key).and_then(|value| value.parse().ok())
+    }
+
+    pub fn echo(&self) -> Option<u32> {
+        self.index("echo")
+    }
+
+    /// The run-identifying entities joined as they appear in the filename,
+    /// e.g. `task-rest_run-1`.
+    pub fn run_key(&self) -> String {
+        self.entities
+            .iter()
+            .filter(|(key, _)| RUN_ENTITIES.contains(&key.as_str()))
+            .map(|(key, value)| format!("{}-{}", key, value))
+            .collect::<Vec<_>>()
+            .join("_")
+    }
+
+    /// Whether the file matches a naming convention such as `bold` or
+    /// `desc-preproc_bold`: the convention's last part is the suffix and any
+    /// entities before it must all be present.
+    pub fn matches_convention(&self, convention: &str) -> bool {
+        let mut parts: Vec<&str> = convention.split('_').collect();
+        let Some(suffix) = parts.pop() else {
+            return false;
+        };
+        suffix == self.suffix
+            && parts.iter().all(|part| match part.split_once('-') {
+                Some((key, value)) => self.get(key) == Some(value),
+                None => false,
+            })
+    }
+
+    /// The filename these entities were parsed from.
+    pub fn file_name(&self) -> String {
+        let mut parts: Vec<String> = self
+            .entities
+            .iter()
+            .map(|(key, value)| format!("{}-{}", key, value))
+            .collect();
+        parts.push(self.suffix.clone());
+        format!("{}{}", parts.join("_"), self.extension)
+    }
+
+    /// The same file with the entities derivatives add removed, i.e. the raw
+    /// file it was made from.
+    pub fn without_derivative_entities(&self) -> BidsEntities {
+        BidsEntities {
+            entities: self
+                .entities
+                .iter()
+                .filter(|(key, _)| !DERIVATIVE_ENTITIES.contains(&key.as_str()))
+                .cloned()
+                .collect(),
+            suffix: self.suffix.clone(),
+            extension: self.extension.clone(),
+        }
+    }
+
+    /// Whether this sidecar applies to the data file `data` under the BIDS
+    /// inheritance principle: the suffix matches and every entity the sidecar
+    /// names is present in `data` with the same value.
+    pub fn applies_to(&self, data: &BidsEntities) -> bool {
+        self.suffix == data.suffix
+            && self
+                .entities
+                .iter()
+                .all(|(key, value)| data.get(key) == Some(value.as_str()))
+    }
+
+    pub fn is_nifti(&self) -> bool {
+        self.extension == ".nii" || self.extension == ".nii.gz"
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.extension == ".json"
+    }
+}
+
+/// Checks a `sub-<label>` or `ses-<label>` directory name.
+pub fn parse_directory_label<'a>(name: &'a str, entity: &str) -> Result<&'a str, String> {
+    let label = name
+        .strip_prefix(entity)
+        .and_then(|rest| rest.strip_prefix('-'))
+        .ok_or_else(|| format!("{}: expected a '{}-<label>' directory", name, entity))?;
+    if label.is_empty() || !label.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!(
+            "{}: the {} label must be non-empty and alphanumeric",
+            name, entity
+        ));
+    }
+    Ok(label)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(file_name: &str) -> BidsEntities {
+        BidsEntities::parse(file_name).unwrap()
+    }
+
+    fn parse_err(file_name: &str) -> String {
+        BidsEntities::parse(file_name).unwrap_err()
+    }
+
+    #[test]
+    fn parses_valid_names() {
+        let entities = parse("sub-01_ses-pre_task-rest_run-1_echo-2_bold.nii.gz");
+        assert_eq!(
+            entities.entities,
+            [
+                ("sub", "01"),
+                ("ses", "pre"),
+                ("task", "rest"),
+                ("run", "1"),
+                ("echo", "2"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+        assert_eq!(entities.suffix, "bold");
+        assert_eq!(entities.extension, ".nii.gz");
+        assert_eq!(entities.echo(), Some(2));
+        assert_eq!(entities.run_key(), "task-rest_run-1");
+        assert_eq!(
+            entities.file_name(),
+            "sub-01_ses-pre_task-rest_run-1_echo-2_bold.nii.gz"
+        );
+        assert!(entities.is_nifti());
+    }
+
+    #[test]
+    fn splits_the_extension_at_the_first_dot() {
+        assert_eq!(parse("sub-01_bold.nii.gz").extension, ".nii.gz");
+        assert_eq!(parse("sub-01_bold.nii").extension, ".nii");
+        assert_eq!(parse("sub-01_bold.json").extension, ".json");
+        assert_eq!(parse("participants").extension, "");
+        assert_eq!(parse("task-rest_bold.json").suffix, "bold");
+    }
+
+    #[test]
+    fn rejects_entities_out_of_order() {
+        assert!(parse_err("sub-01_run-1_task-rest_bold.nii.gz")
+            .contains("'task' must come before 'run'"));
+    }
+
+    #[test]
+    fn rejects_duplicate_entities() {
+        assert!(parse_err("sub-01_echo-1_echo-2_bold.nii.gz").contains("'echo' appears twice"));
+    }
+
+    #[test]
+    fn rejects_non_alphanumeric_labels() {
+        assert!(parse_err("sub-01_task-rest_state_bold.nii").contains("not a key-value entity"));
+        assert!(parse_err("sub-01_task-rest+1_bold.nii").contains("alphanumeric label"));
+        assert!(parse_err("sub-_bold.nii").contains("alphanumeric label"));
+        assert!(parse_err("sub-01_bo+ld.nii").contains("suffix 'bo+ld'"));
+        assert!(parse_err("sub-01_task-rest.nii").contains("missing suffix"));
+        assert!(parse_err("sub-01_foo-bar_bold.nii").contains("unknown entity 'foo'"));
+    }
+
+    #[test]
+    fn rejects_non_integer_indices() {
+        assert!(parse_err("sub-01_echo-a_bold.nii.gz").contains("non-negative integer"));
+        assert!(parse_err("sub-01_run-one_bold.nii.gz").contains("non-negative integer"));
+        assert_eq!(parse("sub-01_echo-03_bold.nii.gz").echo(), Some(3));
+    }
+
+    #[test]
+    fn matches_conventions() {
+        let raw = parse("sub-01_task-rest_echo-1_bold.nii.gz");
+        assert!(raw.matches_convention("bold"));
+        assert!(!raw.matches_convention("desc-preproc_bold"));
+        assert!(!raw.matches_convention("sbref"));
+
+        let preprocessed = parse("sub-01_task-rest_echo-1_desc-preproc_bold.nii.gz");
+        assert!(preprocessed.matches_convention("desc-preproc_bold"));
+        assert!(preprocessed.matches_convention("bold"));
+        assert!(!preprocessed.matches_convention("desc-brain_bold"));
+        assert!(!preprocessed.matches_convention("preproc_bold"));
+    }
+
+    #[test]
+    fn sidecars_apply_when_their_entities_are_a_subset() {
+        let data = parse("sub-01_ses-pre_task-rest_run-1_echo-2_bold.nii.gz");
+        assert!(parse("task-rest_bold.json").applies_to(&data));
+        assert!(parse("sub-01_task-rest_echo-2_bold.json").applies_to(&data));
+        assert!(parse("sub-01_ses-pre_task-rest_run-1_echo-2_bold.json").applies_to(&data));
+        assert!(parse("bold.json").applies_to(&data));
+
+        assert!(!parse("task-rest_echo-1_bold.json").applies_to(&data));
+        assert!(!parse("sub-01_task-rest_acq-fast_bold.json").applies_to(&data));
+        assert!(!parse("task-rest_sbref.json").applies_to(&data));
+    }
+
+    #[test]
+    fn strips_derivative_entities() {
+        let derived = parse("sub-01_task-rest_echo-1_space-MNI_desc-preproc_bold.nii.gz");
+        assert_eq!(
+            derived.without_derivative_entities().file_name(),
+            "sub-01_task-rest_echo-1_bold.nii.gz"
+        );
+    }
+
+    #[test]
+    fn parses_directory_labels() {
+        assert_eq!(parse_directory_label("sub-01", "sub"), Ok("01"));
+        assert!(parse_directory_label("ses-01", "sub").is_err());
+        assert!(parse_directory_label("sub-01_x", "sub").is_err());
+        assert!(parse_directory_label("sub-", "sub").is_err());
+    }
+}
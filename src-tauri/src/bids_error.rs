@@ -0,0 +1,334 @@
+use serde::{Serialize, Serializer};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// The subject and session an error belongs to, read from the `sub-*` and
+/// `ses-*` parts of its path.
+#[derive(Debug, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct BidsContext {
+    pub subject: Option<String>,
+    pub session: Option<String>,
+}
+
+impl BidsContext {
+    pub fn of(path: &Path) -> BidsContext {
+        let mut context = BidsContext::default();
+        for component in path.components() {
+            let name = component.as_os_str().to_string_lossy();
+            if name.starts_with("sub-") && !name.contains('_') {
+                context.subject = Some(name.into_owned());
+            } else if name.starts_with("ses-") && !name.contains('_') {
+                context.session = Some(name.into_owned());
+            }
+        }
+        context
+    }
+}
+
+/// The kinds of I/O failure the frontend offers a fix for; everything else is
+/// `other`.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IoErrorKind {
+    NotFound,
+    PermissionDenied,
+    InvalidData,
+    Other,
+}
+
+impl From<io::ErrorKind> for IoErrorKind {
+    fn from(kind: io::ErrorKind) -> Self {
+        match kind {
+            io::ErrorKind::NotFound => IoErrorKind::NotFound,
+            io::ErrorKind::PermissionDenied => IoErrorKind::PermissionDenied,
+            io::ErrorKind::InvalidData => IoErrorKind::InvalidData,
+            _ => IoErrorKind::Other,
+        }
+    }
+}
+
+/// What the user has to do about an error. Unlike `kind`, the set of
+/// categories stays the same when variants are added, so the frontend can
+/// pick its advice from the category alone.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BidsErrorCategory {
+    /// The chosen folder isn't a dataset; choose another one.
+    WrongFolder,
+    /// The user can't read a file or folder; fix its permissions.
+    Permission,
+    /// A file or folder is not where it should be.
+    Missing,
+    /// A file's contents must be fixed.
+    Malformed,
+    /// A file or folder must be renamed.
+    Naming,
+    /// Anything else, such as a disk error; only the message can help.
+    Other,
+}
+
+/// Why reading a BIDS dataset failed.
+///
+/// Serialised as one flat object: `category`, `kind` (the variant in
+/// snake_case), `path`, `subject` and `session` are always present, the
+/// latter two `null` outside a subject or session folder, followed by the
+/// variant's own fields. For example:
+///
+/// ```json
+/// {
+///   "category": "malformed",
+///   "kind": "json",
+///   "path": "/data/sub-01/func/sub-01_task-rest_echo-1_bold.json",
+///   "line": 3,
+///   "column": 14,
+///   "message": "expected `,` or `}` at line 3 column 14",
+///   "subject": "sub-01",
+///   "session": null
+/// }
+/// ```
+#[derive(Debug, Serialize, Clone)]
+#[serde(remote = "Self", tag = "kind", rename_all = "snake_case")]
+pub enum BidsError {
+    /// The chosen path doesn't exist or isn't a folder.
+    NotADirectory {
+        path: String,
+        #[serde(flatten)]
+        context: BidsContext,
+    },
+    /// Reading a file or listing a directory failed.
+    Io {
+        path: String,
+        io_kind: IoErrorKind,
+        message: String,
+        #[serde(flatten)]
+        context: BidsContext,
+    },
+    /// A JSON file isn't valid JSON or doesn't have the expected shape.
+    /// `line` and `column` point at syntax errors.
+    Json {
+        path: String,
+        line: Option<u32>,
+        column: Option<u32>,
+        message: String,
+        #[serde(flatten)]
+        context: BidsContext,
+    },
+    /// A `.tsv` file is malformed.
+    Table {
+        path: String,
+        message: String,
+        #[serde(flatten)]
+        context: BidsContext,
+    },
+    /// A file or directory name breaks the BIDS naming rules.
+    InvalidName {
+        path: String,
+        message: String,
+        #[serde(flatten)]
+        context: BidsContext,
+    },
+    /// Something the dataset needs is absent, e.g. a sidecar or a
+    /// derivatives pipeline folder.
+    Missing {
+        path: String,
+        message: String,
+        #[serde(flatten)]
+        context: BidsContext,
+    },
+}
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+impl BidsError {
+    pub fn not_a_directory(path: &Path) -> BidsError {
+        BidsError::NotADirectory {
+            path: path_string(path),
+            context: BidsContext::of(path),
+        }
+    }
+
+    pub fn io(path: &Path, error: io::Error) -> BidsError {
+        BidsError::Io {
+            path: path_string(path),
+            io_kind: error.kind().into(),
+            message: error.to_string(),
+            context: BidsContext::of(path),
+        }
+    }
+
+    pub fn json(path: &Path, error: serde_json::Error) -> BidsError {
+        let syntax = !error.is_data();
+        BidsError::Json {
+            path: path_string(path),
+            line: syntax.then(|| error.line() as u32),
+            column: syntax.then(|| error.column() as u32),
+            message: error.to_string(),
+            context: BidsContext::of(path),
+        }
+    }
+
+    /// Valid JSON of the wrong shape, e.g. an array where an object belongs.
+    pub fn json_shape(path: &Path, message: impl Into<String>) -> BidsError {
+        BidsError::Json {
+            path: path_string(path),
+            line: None,
+            column: None,
+            message: message.into(),
+            context: BidsContext::of(path),
+        }
+    }
+
+    pub fn table(path: &Path, message: impl Into<String>) -> BidsError {
+        BidsError::Table {
+            path: path_string(path),
+            message: message.into(),
+            context: BidsContext::of(path),
+        }
+    }
+
+    pub fn invalid_name(path: &Path, message: impl Into<String>) -> BidsError {
+        BidsError::InvalidName {
+            path: path_string(path),
+            message: message.into(),
+            context: BidsContext::of(path),
+        }
+    }
+
+    pub fn missing(path: &Path, message: impl Into<String>) -> BidsError {
+        BidsError::Missing {
+            path: path_string(path),
+            message: message.into(),
+            context: BidsContext::of(path),
+        }
+    }
+
+    pub fn category(&self) -> BidsErrorCategory {
+        match self {
+            BidsError::NotADirectory { .. } => BidsErrorCategory::WrongFolder,
+            BidsError::Io { io_kind, .. } => match io_kind {
+                IoErrorKind::NotFound => BidsErrorCategory::Missing,
+                IoErrorKind::PermissionDenied => BidsErrorCategory::Permission,
+                IoErrorKind::InvalidData => BidsErrorCategory::Malformed,
+                IoErrorKind::Other => BidsErrorCategory::Other,
+            },
+            BidsError::Json { .. } | BidsError::Table { .. } => BidsErrorCategory::Malformed,
+            BidsError::InvalidName { .. } => BidsErrorCategory::Naming,
+            BidsError::Missing { .. } => BidsErrorCategory::Missing,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            BidsError::NotADirectory { path, .. }
+            | BidsError::Io { path, .. }
+            | BidsError::Json { path, .. }
+            | BidsError::Table { path, .. }
+            | BidsError::InvalidName { path, .. }
+            | BidsError::Missing { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for BidsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BidsError::NotADirectory { path, .. } => write!(f, "{} is not a directory", path),
+            BidsError::Io { path, message, .. } => {
+                write!(f, "Failed to read {}: {}", path, message)
+            }
+            BidsError::Json { path, message, .. } => {
+                write!(f, "Invalid JSON in {}: {}", path, message)
+            }
+            BidsError::Table { path, message, .. } => {
+                write!(f, "Invalid table {}: {}", path, message)
+            }
+            BidsError::InvalidName { path, message, .. } => write!(f, "{}: {}", path, message),
+            BidsError::Missing { path, message, .. } => write!(f, "{}: {}", message, path),
+        }
+    }
+}
+
+impl Error for BidsError {}
+
+/// Adds `category` to the fields derived above.
+impl Serialize for BidsError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Categorised<'a> {
+            category: BidsErrorCategory,
+            #[serde(flatten, with = "BidsError")]
+            error: &'a BidsError,
+        }
+        Categorised {
+            category: self.category(),
+            error: self,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Lets callers that still report plain text use `?` on BIDS results.
+impl From<BidsError> for String {
+    fn from(error: BidsError) -> String {
+        error.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn serialises_as_a_flat_object_with_a_category() {
+        let path = Path::new("/data/sub-01/func/sub-01_task-rest_echo-1_bold.json");
+        let syntax = serde_json::from_str::<serde_json::Value>("{\n\"a\": 1,\n}").unwrap_err();
+        let message = syntax.to_string();
+        let error = BidsError::json(path, syntax);
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({
+                "category": "malformed",
+                "kind": "json",
+                "path": "/data/sub-01/func/sub-01_task-rest_echo-1_bold.json",
+                "line": 3,
+                "column": 1,
+                "message": message,
+                "subject": "sub-01",
+                "session": null,
+            })
+        );
+    }
+
+    #[test]
+    fn categorises_io_errors_by_kind() {
+        let path = Path::new("/data/sub-01/ses-pre");
+        let io_error = |kind| BidsError::io(path, io::Error::new(kind, "failed"));
+        assert_eq!(
+            io_error(io::ErrorKind::PermissionDenied).category(),
+            BidsErrorCategory::Permission
+        );
+        assert_eq!(
+            io_error(io::ErrorKind::NotFound).category(),
+            BidsErrorCategory::Missing
+        );
+        assert_eq!(
+            io_error(io::ErrorKind::Interrupted).category(),
+            BidsErrorCategory::Other
+        );
+        let value = serde_json::to_value(io_error(io::ErrorKind::PermissionDenied)).unwrap();
+        assert_eq!(value["category"], "permission");
+        assert_eq!(value["io_kind"], "permission_denied");
+        assert_eq!(value["session"], "ses-pre");
+    }
+
+    #[test]
+    fn is_a_standard_error() {
+        let error: Box<dyn Error> = Box::new(BidsError::not_a_directory(Path::new("/data")));
+        assert_eq!(error.to_string(), "/data is not a directory");
+    }
+}
@@ -0,0 +1,322 @@
+use crate::bids::read_sidecar;
+use crate::bids_error::BidsError;
+use crate::nifti::{self, NiftiHeader};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+const INDEX_DIR_NAME: &str = "bids_index";
+
+static INDEX_DIR: OnceCell<PathBuf> = OnceCell::new();
+/// Held while an index is written, since scans of the same dataset can run at
+/// the same time.
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Sets where indexes are stored, once at startup. Without it nothing is
+/// cached and every scan reads the dataset in full.
+pub fn init(data_dir: Option<PathBuf>) {
+    if let Some(data_dir) = data_dir {
+        let _ = INDEX_DIR.set(data_dir.join(INDEX_DIR_NAME));
+    }
+}
+
+/// Deletes the index of the dataset at `root`, so the next scan rereads
+/// every file.
+pub fn clear(root: &Path) {
+    if let Some(path) = index_path(root) {
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                println!("Error clearing BIDS index {:?}: {}", path, e)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// One file per dataset, named after a hash of its root so any path gives a
+/// valid file name. The root is also stored inside to rule out collisions.
+fn index_path(root: &Path) -> Option<PathBuf> {
+    let dir = INDEX_DIR.get()?;
+    // FNV-1a, which unlike `DefaultHasher` is stable across Rust releases.
+    let hash = root
+        .to_string_lossy()
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    Some(dir.join(format!("{:016x}.json", hash)))
+}
+
+/// What a file looked like when it was parsed. A file whose modification
+/// time and size are unchanged is assumed to have the same content.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    size: u64,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Stamp> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Stamp {
+            modified: metadata.modified().ok(),
+            size: metadata.len(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry<T> {
+    stamp: Stamp,
+    value: T,
+}
+
+/// The parsed sidecars and NIfTI headers of a dataset, including those of
+/// its derivatives, and the contents of the directories sidecars are looked
+/// up in, from earlier scans. Failures aren't cached, so a broken file is
+/// read again until it is fixed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BidsIndex {
+    root: PathBuf,
+    sidecars: BTreeMap<PathBuf, Entry<Map<String, Value>>>,
+    headers: BTreeMap<PathBuf, Entry<NiftiHeader>>,
+    /// Sorted names of the visible entries of each directory.
+    #[serde(default)]
+    listings: BTreeMap<PathBuf, Entry<Vec<String>>>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    /// Directories listed by this scan, whose listings are up to date.
+    #[serde(skip)]
+    listed: BTreeSet<PathBuf>,
+}
+
+impl BidsIndex {
+    /// Loads the index of the dataset at `root`, or starts an empty one when
+    /// there is none or it can't be read.
+    pub fn load(root: &Path) -> BidsIndex {
+        let path = index_path(root);
+        let mut index = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str::<BidsIndex>(&contents).ok())
+            .filter(|index| index.root == root)
+            .unwrap_or_default();
+        index.root = root.to_path_buf();
+        index.path = path;
+        index
+    }
+
+    pub fn sidecar(&mut self, path: &Path) -> Result<Map<String, Value>, BidsError> {
+        let stamp = Stamp::of(path);
+        if let (Some(entry), Some(stamp)) = (self.sidecars.get(path), &stamp) {
+            if entry.stamp == *stamp {
+                return Ok(entry.value.clone());
+            }
+        }
+
+        let fields = read_sidecar(path)?;
+        if let Some(stamp) = stamp {
+            self.sidecars.insert(
+                path.to_path_buf(),
+                Entry {
+                    stamp,
+                    value: fields.clone(),
+                },
+            );
+        }
+        Ok(fields)
+    }
+
+    pub fn header(&mut self, path: &Path) -> Result<NiftiHeader, String> {
+        let stamp = Stamp::of(path);
+        if let (Some(entry), Some(stamp)) = (self.headers.get(path), &stamp) {
+            if entry.stamp == *stamp {
+                return Ok(entry.value.clone());
+            }
+        }
+
+        let header = nifti::read_header(path)?;
+        if let Some(stamp) = stamp {
+            self.headers.insert(
+                path.to_path_buf(),
+                Entry {
+                    stamp,
+                    value: header.clone(),
+                },
+            );
+        }
+        Ok(header)
+    }
+
+    /// Names of the visible entries of `dir`, sorted. Adding, removing or
+    /// renaming an entry changes the directory's modification time, so the
+    /// cached names are reused until it does.
+    pub fn listing(&mut self, dir: &Path) -> Result<Vec<String>, BidsError> {
+        // Without a modification time a changed directory can't be told apart.
+        let stamp = Stamp::of(dir).filter(|stamp| stamp.modified.is_some());
+        if let (Some(entry), Some(stamp)) = (self.listings.get(dir), &stamp) {
+            if entry.stamp == *stamp {
+                self.listed.insert(dir.to_path_buf());
+                return Ok(entry.value.clone());
+            }
+        }
+
+        let mut names = Vec::new();
+        let entries = fs::read_dir(dir).map_err(|e| {
+            self.listings.remove(dir);
+            BidsError::io(dir, e)
+        })?;
+        for entry in entries {
+            let entry = entry.map_err(|e| BidsError::io(dir, e))?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with('.') {
+                names.push(name);
+            }
+        }
+        names.sort();
+        if let Some(stamp) = stamp {
+            self.listings.insert(
+                dir.to_path_buf(),
+                Entry {
+                    stamp,
+                    value: names.clone(),
+                },
+            );
+            self.listed.insert(dir.to_path_buf());
+        }
+        Ok(names)
+    }
+
+    /// Writes the index back, dropping entries for files this scan found
+    /// deleted. Only directories the scan listed are checked; everything
+    /// else is kept as it is, since a raw and a derivatives scan of the same
+    /// dataset read different files.
+    pub fn save(mut self) {
+        let Some(path) = self.path.take() else {
+            return;
+        };
+        let listed = std::mem::take(&mut self.listed);
+        let listings = &self.listings;
+        let stale_listings: Vec<PathBuf> = listings
+            .keys()
+            .filter(|dir| !still_listed(dir, &listed, listings))
+            .cloned()
+            .collect();
+        self.sidecars
+            .retain(|file, _| still_listed(file, &listed, listings));
+        self.headers
+            .retain(|file, _| still_listed(file, &listed, listings));
+        for dir in stale_listings {
+            self.listings.remove(&dir);
+        }
+
+        if let Err(e) = write_atomically(&path, &self) {
+            println!("Error saving BIDS index {:?}: {}", path, e);
+        }
+    }
+}
+
+/// Whether `path` is still there according to the nearest directory above it
+/// that this scan listed. Paths under directories the scan didn't look at
+/// are assumed to be.
+fn still_listed(
+    path: &Path,
+    listed: &BTreeSet<PathBuf>,
+    listings: &BTreeMap<PathBuf, Entry<Vec<String>>>,
+) -> bool {
+    let mut child = path;
+    for dir in path.ancestors().skip(1) {
+        if listed.contains(dir) {
+            let (Some(listing), Some(name)) = (listings.get(dir), child.file_name()) else {
+                return true;
+            };
+            let name = name.to_string_lossy();
+            return listing
+                .value
+                .binary_search_by(|entry| entry.as_str().cmp(&name))
+                .is_ok();
+        }
+        child = dir;
+    }
+    true
+}
+
+/// Writes to a temporary file first, so a scan running at the same time
+/// never reads half an index.
+fn write_atomically(path: &Path, index: &BidsIndex) -> Result<(), String> {
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create index directory {:?}: {}", parent, e))?;
+    }
+    let contents =
+        serde_json::to_string(index).map_err(|e| format!("Failed to serialise index: {}", e))?;
+    let temporary = path.with_extension("json.tmp");
+    fs::write(&temporary, contents).map_err(|e| e.to_string())?;
+    fs::rename(&temporary, path).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bids_tables::tests::TempDir;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn dataset(name: &str) -> TempDir {
+        let dir = TempDir::new(name);
+        fs::create_dir_all(dir.0.join("sub-01").join("func")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn lists_visible_entries_and_rereads_changed_directories() {
+        let dir = dataset("index-listing");
+        let root = &dir.0;
+        dir.write("task-rest_bold.json", "{}");
+        dir.write(".DS_Store", "");
+        // Backdate the root so adding a file changes its modification time
+        // even on file systems with coarse timestamps.
+        fs::File::open(root)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(1_000_000))
+            .unwrap();
+
+        let mut index = BidsIndex::default();
+        assert_eq!(
+            index.listing(root).unwrap(),
+            ["sub-01", "task-rest_bold.json"]
+        );
+        dir.write("dataset_description.json", "{}");
+        assert_eq!(
+            index.listing(root).unwrap(),
+            ["dataset_description.json", "sub-01", "task-rest_bold.json"]
+        );
+        assert!(index.listing(&root.join("sub-02")).is_err());
+    }
+
+    #[test]
+    fn prunes_only_entries_under_listed_directories() {
+        let dir = dataset("index-prune");
+        let root = &dir.0;
+        let func = root.join("sub-01").join("func");
+        fs::write(func.join("sub-01_task-rest_echo-1_bold.json"), "{}").unwrap();
+
+        let mut index = BidsIndex::default();
+        index.listing(root).unwrap();
+        index.listing(&func).unwrap();
+        let listed = |file: &Path| still_listed(file, &index.listed, &index.listings);
+
+        assert!(listed(&func.join("sub-01_task-rest_echo-1_bold.json")));
+        assert!(!listed(&func.join("sub-01_task-rest_echo-2_bold.json")));
+        // The root was listed without sub-02, so everything below it is gone.
+        assert!(!listed(&root.join("sub-02").join("func").join("x.json")));
+        // Nothing under sub-01/anat was looked at.
+        assert!(listed(&root.join("sub-01").join("anat").join("x.json")));
+        assert!(listed(Path::new("/elsewhere/x.json")));
+    }
+}
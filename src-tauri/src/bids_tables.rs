@@ -0,0 +1,313 @@
+use crate::bids_error::BidsError;
+use serde_json::{Map, Number, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// One row of a BIDS tabular file, keyed by column name. Numeric columns hold
+/// numbers, everything else strings; `n/a` becomes `null`.
+pub type Attributes = BTreeMap<String, Value>;
+
+/// The rows of a `participants.tsv`, `sessions.tsv` or `scans.tsv` file,
+/// keyed by their identifying column.
+#[derive(Debug, Default)]
+pub struct Table {
+    rows: BTreeMap<String, Attributes>,
+}
+
+impl Table {
+    /// The row identified by `id`, or no attributes when the table doesn't
+    /// list it.
+    pub fn get(&self, id: &str) -> Attributes {
+        self.rows.get(id).cloned().unwrap_or_default()
+    }
+}
+
+enum ColumnType {
+    Integer,
+    Number,
+    Text,
+}
+
+/// Reads `tsv`, keying rows by `key_column`. Column types come from the data
+/// dictionary next to the file, or from `root_dictionary` (e.g.
+/// `participants.json` at the dataset root) when there is none. A missing
+/// file gives an empty table, since all three files are optional.
+pub fn read_table(
+    tsv: &Path,
+    key_column: &str,
+    root_dictionary: &Path,
+) -> Result<Table, BidsError> {
+    if !tsv.is_file() {
+        return Ok(Table::default());
+    }
+    let content = fs::read_to_string(tsv).map_err(|e| BidsError::io(tsv, e))?;
+    // Numbered before blank lines are skipped, so errors point at the line
+    // in the file.
+    let mut lines = content
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty());
+
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or_else(|| BidsError::table(tsv, "The table has no header row"))?
+        .1
+        .split('\t')
+        .collect();
+    let key_index = header
+        .iter()
+        .position(|column| *column == key_column)
+        .ok_or_else(|| {
+            BidsError::table(tsv, format!("The table has no '{}' column", key_column))
+        })?;
+
+    let mut cells: Vec<Vec<&str>> = Vec::new();
+    for (index, line) in lines {
+        let row: Vec<&str> = line.split('\t').collect();
+        if row.len() != header.len() {
+            return Err(BidsError::table(
+                tsv,
+                format!(
+                    "Row {} has {} columns but the header has {}",
+                    index + 1,
+                    row.len(),
+                    header.len()
+                ),
+            ));
+        }
+        cells.push(row);
+    }
+
+    let sidecar = tsv.with_extension("json");
+    let dictionary = read_dictionary(if sidecar.is_file() {
+        &sidecar
+    } else {
+        root_dictionary
+    });
+    let types: Vec<ColumnType> = header
+        .iter()
+        .enumerate()
+        .map(|(index, column)| {
+            if index == key_index {
+                return ColumnType::Text;
+            }
+            column_type(column, cells.iter().map(|row| row[index]), &dictionary)
+        })
+        .collect();
+
+    let mut table = Table::default();
+    for row in cells {
+        let attributes = header
+            .iter()
+            .zip(&row)
+            .zip(&types)
+            .map(|((column, cell), column_type)| {
+                (column.to_string(), parse_cell(cell, column_type))
+            })
+            .collect();
+        table.rows.insert(row[key_index].to_string(), attributes);
+    }
+    Ok(table)
+}
+
+fn read_dictionary(path: &Path) -> Map<String, Value> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return Map::new();
+    };
+    match serde_json::from_str(&content) {
+        Ok(Value::Object(dictionary)) => dictionary,
+        _ => {
+            println!("Ignoring malformed data dictionary {:?}", path);
+            Map::new()
+        }
+    }
+}
+
+/// Columns the dictionary describes with `Levels` are categorical even when
+/// their codes look like numbers; others are numeric when every value is.
+fn column_type<'a>(
+    column: &str,
+    values: impl Iterator<Item = &'a str>,
+    dictionary: &Map<String, Value>,
+) -> ColumnType {
+    if dictionary
+        .get(column)
+        .and_then(|description| description.get("Levels"))
+        .is_some()
+    {
+        return ColumnType::Text;
+    }
+
+    let present: Vec<&str> = values.filter(|value| *value != "n/a").collect();
+    if present.is_empty() {
+        ColumnType::Text
+    } else if present.iter().all(|value| value.parse::<i64>().is_ok()) {
+        ColumnType::Integer
+    } else if present.iter().all(|value| value.parse::<f64>().is_ok()) {
+        ColumnType::Number
+    } else {
+        ColumnType::Text
+    }
+}
+
+fn parse_cell(cell: &str, column_type: &ColumnType) -> Value {
+    if cell == "n/a" {
+        return Value::Null;
+    }
+    match column_type {
+        ColumnType::Integer => cell.parse::<i64>().map(Value::from).unwrap_or(Value::Null),
+        ColumnType::Number => cell
+            .parse::<f64>()
+            .ok()
+            .and_then(Number::from_f64)
+            .map(Value::Number)
+            .unwrap_or(Value::Null),
+        ColumnType::Text => Value::String(cell.to_string()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    /// A directory of its own for each test, removed when the test ends.
+    pub(crate) struct TempDir(pub(crate) PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new(name: &str) -> TempDir {
+            let dir =
+                std::env::temp_dir().join(format!("bids-test-{}-{}", std::process::id(), name));
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        pub(crate) fn write(&self, name: &str, content: &str) -> PathBuf {
+            let path = self.0.join(name);
+            fs::write(&path, content).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn read(dir: &TempDir, tsv: &str) -> Result<Table, BidsError> {
+        read_table(
+            &dir.write("participants.tsv", tsv),
+            "participant_id",
+            &dir.0.join("dataset_dictionary.json"),
+        )
+    }
+
+    #[test]
+    fn types_columns_from_their_values() {
+        let dir = TempDir::new("types");
+        let table = read(
+            &dir,
+            "participant_id\tage\theight\tgroup\r\nsub-01\t34\t1.72\tpatient\r\nsub-02\tn/a\t1.8\tcontrol\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            table.get("sub-01"),
+            json!({ "participant_id": "sub-01", "age": 34, "height": 1.72, "group": "patient" })
+                .as_object()
+                .unwrap()
+                .clone()
+                .into_iter()
+                .collect::<Attributes>()
+        );
+        assert_eq!(table.get("sub-02")["age"], Value::Null);
+        assert_eq!(table.get("sub-02")["height"], json!(1.8));
+        assert!(table.get("sub-03").is_empty());
+    }
+
+    #[test]
+    fn n_a_values_do_not_decide_the_column_type() {
+        let dir = TempDir::new("na");
+        let table = read(
+            &dir,
+            "participant_id\tage\tscore\tnotes\nsub-01\tn/a\tn/a\tn/a\nsub-02\t9\t3\tn/a\nsub-03\tforty\t4\tn/a\n",
+        )
+        .unwrap();
+        assert_eq!(table.get("sub-02")["score"], json!(3));
+        assert_eq!(table.get("sub-01")["score"], Value::Null);
+        // One non-number makes the whole column text.
+        assert_eq!(table.get("sub-02")["age"], json!("9"));
+        assert_eq!(table.get("sub-01")["age"], Value::Null);
+        assert_eq!(table.get("sub-01")["notes"], Value::Null);
+    }
+
+    #[test]
+    fn levels_in_the_dictionary_keep_codes_as_text() {
+        let dir = TempDir::new("levels");
+        dir.write(
+            "participants.json",
+            r#"{"group": {"Levels": {"1": "patient", "2": "control"}}, "age": {"Units": "years"}}"#,
+        );
+        let table = read(&dir, "participant_id\tgroup\tage\nsub-01\t1\t34\n").unwrap();
+        assert_eq!(table.get("sub-01")["group"], json!("1"));
+        assert_eq!(table.get("sub-01")["age"], json!(34));
+    }
+
+    #[test]
+    fn falls_back_to_the_root_dictionary() {
+        let dir = TempDir::new("root-dictionary");
+        dir.write(
+            "dataset_dictionary.json",
+            r#"{"group": {"Levels": {"1": "patient"}}}"#,
+        );
+        let table = read(&dir, "participant_id\tgroup\nsub-01\t1\n").unwrap();
+        assert_eq!(table.get("sub-01")["group"], json!("1"));
+    }
+
+    #[test]
+    fn ignores_malformed_dictionaries() {
+        let dir = TempDir::new("bad-dictionary");
+        dir.write("participants.json", "{not json");
+        let table = read(&dir, "participant_id\tgroup\nsub-01\t1\n").unwrap();
+        assert_eq!(table.get("sub-01")["group"], json!(1));
+    }
+
+    #[test]
+    fn rejects_ragged_rows() {
+        let dir = TempDir::new("ragged");
+        let error = read(&dir, "participant_id\tage\n\nsub-01\t34\nsub-02\n")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("Row 4 has 1 columns but the header has 2"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn requires_the_key_column() {
+        let dir = TempDir::new("key");
+        let error = read(&dir, "subject\tage\nsub-01\t34\n")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("no 'participant_id' column"), "{}", error);
+        let error = read(&dir, "\n\n").unwrap_err().to_string();
+        assert!(error.contains("no header row"), "{}", error);
+    }
+
+    #[test]
+    fn missing_tables_are_empty() {
+        let dir = TempDir::new("missing");
+        let table = read_table(
+            &dir.0.join("sessions.tsv"),
+            "session_id",
+            &dir.0.join("sessions.json"),
+        )
+        .unwrap();
+        assert!(table.get("ses-01").is_empty());
+    }
+}
@@ -0,0 +1,388 @@
+use crate::bids::{self, BidsStructure, DerivativeQuery, RunRef};
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Component, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+use tauri::Manager;
+
+/// How long a file must stay untouched before its change is applied, so
+/// copying a subject in or tedana writing its outputs gives one update, not
+/// dozens.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Folders at the top of a dataset that never hold subjects to process.
+const SKIPPED_DIRS: &[&str] = &["derivatives", "sourcedata", "code"];
+
+/// The running watches by id. Each view watches with its own id, so one
+/// view stopping its watch leaves the others running.
+static WATCHES: Lazy<Mutex<Watches>> = Lazy::new(|| Mutex::new(Watches::default()));
+
+#[derive(Default)]
+struct Watches {
+    next_id: u64,
+    /// Dropping a debouncer stops its file system watch.
+    debouncers: HashMap<u64, Debouncer<RecommendedWatcher>>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SessionRef {
+    pub subject: String,
+    pub session: String,
+}
+
+/// What changed between two extractions of a dataset. Everything inside an
+/// added or removed subject or session is listed too, so each list can be
+/// applied on its own.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct StructureDiff {
+    pub added_subjects: Vec<String>,
+    pub removed_subjects: Vec<String>,
+    pub added_sessions: Vec<SessionRef>,
+    pub removed_sessions: Vec<SessionRef>,
+    pub added_runs: Vec<RunRef>,
+    pub removed_runs: Vec<RunRef>,
+}
+
+/// The payload of `bids-structure-changed`.
+#[derive(Debug, Serialize)]
+pub struct StructureChange<'a> {
+    /// The id `watch_dataset` returned, telling views which change is theirs.
+    pub watch_id: u64,
+    pub path: &'a str,
+    pub diff: StructureDiff,
+    /// The dataset as it is now, with only the changed subjects reread.
+    pub structure: &'a BidsStructure,
+}
+
+/// Starts watching the dataset at `path`, whose current contents are
+/// `structure`, and returns the watch's id. `bids-structure-changed` is
+/// emitted with that id whenever subjects, sessions or runs change on disk.
+pub fn watch_dataset(
+    app: tauri::AppHandle,
+    path: String,
+    convention: String,
+    derivatives: Option<DerivativeQuery>,
+    structure: BidsStructure,
+) -> Result<u64, String> {
+    let watch = Watch {
+        path,
+        convention,
+        derivatives,
+    };
+    let (sender, receiver) = mpsc::channel();
+    let mut debouncer = new_debouncer(DEBOUNCE, sender)
+        .map_err(|e| format!("Failed to watch {}: {}", watch.path, e))?;
+    for root in watch.roots() {
+        debouncer
+            .watcher()
+            .watch(&root, RecursiveMode::Recursive)
+            .map_err(|e| format!("Failed to watch {:?}: {}", root, e))?;
+    }
+
+    let id = {
+        let mut watches = WATCHES.lock().unwrap_or_else(|e| e.into_inner());
+        let id = watches.next_id;
+        watches.next_id += 1;
+        watches.debouncers.insert(id, debouncer);
+        id
+    };
+    std::thread::spawn(move || watch.run(&app, id, structure, receiver));
+    Ok(id)
+}
+
+/// Stops the watch with the given id, if it is still running.
+pub fn unwatch_dataset(id: u64) {
+    // The debouncer owns the sending end of the watch's channel, so dropping
+    // it also ends the watch's thread.
+    WATCHES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .debouncers
+        .remove(&id);
+}
+
+struct Watch {
+    path: String,
+    convention: String,
+    derivatives: Option<DerivativeQuery>,
+}
+
+impl Watch {
+    fn run(
+        &self,
+        app: &tauri::AppHandle,
+        id: u64,
+        mut structure: BidsStructure,
+        events: mpsc::Receiver<DebounceEventResult>,
+    ) {
+        // Set when rereading failed part way, after which only a full reread
+        // gives a structure to diff against.
+        let mut stale = false;
+
+        while let Ok(first) = events.recv() {
+            // Batches that arrived while the last one was applied are applied
+            // together.
+            let mut paths = Vec::new();
+            for result in std::iter::once(first).chain(events.try_iter()) {
+                match result {
+                    Ok(batch) => paths.extend(batch.into_iter().map(|event| event.path)),
+                    Err(e) => println!("Error watching {}: {}", self.path, e),
+                }
+            }
+
+            let before = Contents::of(&structure);
+            let updated = match self.changed_subjects(&paths) {
+                Some(subjects) if subjects.is_empty() => continue,
+                Some(subjects) if !stale => bids::refresh_subjects(
+                    &mut structure,
+                    &self.path,
+                    &self.convention,
+                    self.derivatives.as_ref(),
+                    &subjects,
+                )
+                .map_err(String::from),
+                _ => self.extract().map(|updated| structure = updated),
+            };
+            if let Err(e) = updated {
+                println!("Error rereading watched dataset: {}", e);
+                stale = true;
+                continue;
+            }
+            stale = false;
+
+            let change = StructureChange {
+                watch_id: id,
+                path: &self.path,
+                diff: StructureDiff::between(&before, &Contents::of(&structure)),
+                structure: &structure,
+            };
+            let _ = app.emit_all("bids-structure-changed", &change);
+        }
+    }
+
+    fn extract(&self) -> Result<BidsStructure, String> {
+        match &self.derivatives {
+            Some(query) => bids::extract_derivatives_structure(&self.path, query),
+            None => bids::extract_bids_structure(&self.path, &self.convention),
+        }
+        .map_err(String::from)
+    }
+
+    /// The directories whose contents make up the dataset: for derivatives,
+    /// the pipeline folder and the raw dataset it inherits from.
+    fn roots(&self) -> Vec<PathBuf> {
+        let dataset_root = PathBuf::from(&self.path);
+        match &self.derivatives {
+            Some(query) => vec![query.pipeline_dir(&dataset_root), dataset_root],
+            None => vec![dataset_root],
+        }
+    }
+
+    /// The subjects whose folders contain `paths`, or `None` when one of
+    /// them applies to every subject, such as `participants.tsv` or a
+    /// root-level sidecar.
+    fn changed_subjects(&self, paths: &[PathBuf]) -> Option<BTreeSet<String>> {
+        let roots = self.roots();
+        let mut subjects = BTreeSet::new();
+        for path in paths {
+            // The pipeline folder comes first, as it can be inside the
+            // dataset.
+            let Some(relative) = roots.iter().find_map(|root| path.strip_prefix(root).ok()) else {
+                continue;
+            };
+            let mut names = relative
+                .components()
+                .filter_map(|component| match component {
+                    Component::Normal(name) => Some(name.to_string_lossy()),
+                    _ => None,
+                });
+            let Some(top) = names.next() else {
+                continue;
+            };
+            let nested = names.next().is_some();
+            if top.starts_with("sub-") {
+                subjects.insert(top.into_owned());
+            } else if !nested
+                && !SKIPPED_DIRS.contains(&top.as_ref())
+                && (!top.starts_with('.') || top == ".bidsignore")
+            {
+                return None;
+            }
+        }
+        Some(subjects)
+    }
+}
+
+/// The subjects, sessions and runs of a structure, for comparing two of them.
+#[derive(Default)]
+struct Contents {
+    subjects: BTreeSet<String>,
+    sessions: BTreeSet<(String, String)>,
+    runs: BTreeSet<(String, String, String)>,
+}
+
+impl Contents {
+    fn of(structure: &BidsStructure) -> Contents {
+        let mut contents = Contents::default();
+        for subject in &structure.subjects {
+            contents.subjects.insert(subject.name.clone());
+            for session in &subject.sessions {
+                contents
+                    .sessions
+                    .insert((subject.name.clone(), session.name.clone()));
+                for run in &session.runs {
+                    contents.runs.insert((
+                        subject.name.clone(),
+                        session.name.clone(),
+                        run.key.clone(),
+                    ));
+                }
+            }
+        }
+        contents
+    }
+}
+
+impl StructureDiff {
+    fn between(before: &Contents, after: &Contents) -> StructureDiff {
+        let session = |(subject, session): &(String, String)| SessionRef {
+            subject: subject.clone(),
+            session: session.clone(),
+        };
+        let run = |(subject, session, run): &(String, String, String)| RunRef {
+            subject: subject.clone(),
+            session: session.clone(),
+            run: run.clone(),
+        };
+        StructureDiff {
+            added_subjects: after
+                .subjects
+                .difference(&before.subjects)
+                .cloned()
+                .collect(),
+            removed_subjects: before
+                .subjects
+                .difference(&after.subjects)
+                .cloned()
+                .collect(),
+            added_sessions: after
+                .sessions
+                .difference(&before.sessions)
+                .map(session)
+                .collect(),
+            removed_sessions: before
+                .sessions
+                .difference(&after.sessions)
+                .map(session)
+                .collect(),
+            added_runs: after.runs.difference(&before.runs).map(run).collect(),
+            removed_runs: before.runs.difference(&after.runs).map(run).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watch(derivatives: Option<&str>) -> Watch {
+        Watch {
+            path: "/data/ds".to_string(),
+            convention: "bold".to_string(),
+            derivatives: derivatives.map(|pipeline| DerivativeQuery {
+                pipeline: pipeline.to_string(),
+                space: None,
+                desc: None,
+            }),
+        }
+    }
+
+    fn changed(watch: &Watch, paths: &[&str]) -> Option<Vec<String>> {
+        let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+        watch
+            .changed_subjects(&paths)
+            .map(|subjects| subjects.into_iter().collect())
+    }
+
+    #[test]
+    fn groups_changes_by_subject() {
+        let raw = watch(None);
+        assert_eq!(
+            changed(
+                &raw,
+                &[
+                    "/data/ds/sub-02/func/sub-02_task-rest_echo-1_bold.nii.gz",
+                    "/data/ds/sub-01/ses-2",
+                    "/data/ds/sub-02/func/sub-02_task-rest_echo-2_bold.json",
+                    "/data/ds/sub-03",
+                ]
+            ),
+            Some(vec![
+                "sub-01".to_string(),
+                "sub-02".to_string(),
+                "sub-03".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn rereads_everything_when_a_dataset_file_changes() {
+        let raw = watch(None);
+        assert_eq!(changed(&raw, &["/data/ds/participants.tsv"]), None);
+        assert_eq!(
+            changed(
+                &raw,
+                &["/data/ds/sub-01/anat", "/data/ds/task-rest_bold.json"]
+            ),
+            None
+        );
+        assert_eq!(changed(&raw, &["/data/ds/.bidsignore"]), None);
+    }
+
+    #[test]
+    fn ignores_folders_without_subjects() {
+        let raw = watch(None);
+        assert_eq!(
+            changed(
+                &raw,
+                &[
+                    "/data/ds",
+                    "/data/ds/derivatives",
+                    "/data/ds/derivatives/tedana/sub-01/func/desc-optcom_bold.nii.gz",
+                    "/data/ds/sourcedata/dicoms/001.dcm",
+                    "/data/ds/.DS_Store",
+                    "/data/ds/.git/index",
+                    "/elsewhere/sub-01",
+                ]
+            ),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn maps_pipeline_changes_to_subjects() {
+        let derivatives = watch(Some("fmriprep"));
+        assert_eq!(
+            changed(
+                &derivatives,
+                &[
+                    "/data/ds/derivatives/fmriprep/sub-04/func/x_bold.nii.gz",
+                    "/data/ds/sub-05/func/sub-05_task-rest_echo-1_bold.json",
+                    "/data/ds/derivatives/tedana/sub-06/report.html",
+                ]
+            ),
+            Some(vec!["sub-04".to_string(), "sub-05".to_string()])
+        );
+        assert_eq!(
+            changed(
+                &derivatives,
+                &["/data/ds/derivatives/fmriprep/dataset_description.json"]
+            ),
+            None
+        );
+    }
+}
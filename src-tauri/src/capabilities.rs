@@ -0,0 +1,480 @@
+use crate::args::TedanaArgs;
+use crate::python_env::PythonEnvironment;
+use crate::tedana::{self, ConfigIssue, Severity};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// A tedana release number such as `24.0.1`. Pre-release and local suffixes
+/// are ignored.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TedanaVersion(pub u32, pub u32, pub u32);
+
+impl TedanaVersion {
+    pub fn parse(version: &str) -> Option<TedanaVersion> {
+        let mut parts = version
+            .trim()
+            .trim_start_matches('v')
+            .split(|c: char| !c.is_ascii_digit())
+            .take(3)
+            .map(|part| part.parse::<u32>().ok());
+        Some(TedanaVersion(
+            parts.next()??,
+            parts.next().flatten().unwrap_or(0),
+            parts.next().flatten().unwrap_or(0),
+        ))
+    }
+}
+
+/// Built-in decision trees with the release that introduced them and, for
+/// retired names, the first release without them. `--tree` takes a
+/// free-form value, so its names never show up in `--help`.
+const TREES: &[(&str, TedanaVersion, Option<TedanaVersion>)] = &[
+    (
+        "kundu",
+        TedanaVersion(23, 0, 0),
+        Some(TedanaVersion(24, 0, 0)),
+    ),
+    ("minimal", TedanaVersion(23, 0, 0), None),
+    ("meica", TedanaVersion(24, 0, 0), None),
+    ("tedana_orig", TedanaVersion(24, 0, 0), None),
+];
+
+/// Trees that were renamed between releases, as (newer name, older name).
+const TREE_ALIASES: &[(&str, &str)] = &[("tedana_orig", "kundu")];
+
+/// What the installed tedana accepts, read from its `--help` output.
+#[derive(Debug, Serialize, Clone)]
+pub struct TedanaCapabilities {
+    pub version: Option<TedanaVersion>,
+    pub version_string: String,
+    /// Every long option tedana lists, with its allowed values when argparse
+    /// prints them as `{a,b,c}`.
+    pub options: BTreeMap<String, Option<Vec<String>>>,
+}
+
+/// Modification times of the interpreter and of the `tedana` entry point
+/// next to it. Installing or upgrading tedana rewrites the entry point, and
+/// recreating the environment replaces the interpreter.
+type InstallStamp = (Option<SystemTime>, Option<SystemTime>);
+
+static CAPABILITIES: Lazy<Mutex<HashMap<String, (InstallStamp, TedanaCapabilities)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn install_stamp(python_path: &str) -> InstallStamp {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let interpreter = Path::new(python_path);
+    let entry_point = interpreter.parent().map(|bin_dir| {
+        bin_dir.join(if cfg!(windows) {
+            "tedana.exe"
+        } else {
+            "tedana"
+        })
+    });
+    (
+        modified(interpreter),
+        entry_point.as_deref().and_then(modified),
+    )
+}
+
+impl TedanaCapabilities {
+    /// Probes the interpreter's tedana and caches the result until tedana or
+    /// the interpreter is reinstalled.
+    pub fn for_python(python_path: &str) -> Result<TedanaCapabilities, String> {
+        let stamp = install_stamp(python_path);
+        if let Some((cached_stamp, capabilities)) = CAPABILITIES.lock().unwrap().get(python_path) {
+            if *cached_stamp == stamp {
+                return Ok(capabilities.clone());
+            }
+        }
+
+        let version_string = tedana::check_tedana_installation(python_path.to_string(), None)?;
+        let environment = PythonEnvironment::detect(python_path, None)?;
+        let output = environment
+            .tedana_command()
+            .arg("--help")
+            .output()
+            .map_err(|e| format!("Failed to run tedana --help: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "tedana --help failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        let capabilities = TedanaCapabilities {
+            version: TedanaVersion::parse(&version_string),
+            version_string,
+            options: parse_help(&String::from_utf8_lossy(&output.stdout)),
+        };
+        CAPABILITIES
+            .lock()
+            .unwrap()
+            .insert(python_path.to_string(), (stamp, capabilities.clone()));
+        Ok(capabilities)
+    }
+
+    pub fn supports(&self, flag: &str) -> bool {
+        self.options.contains_key(flag)
+    }
+
+    fn allows(&self, flag: &str, value: &str) -> bool {
+        match self.options.get(flag) {
+            Some(Some(choices)) => choices.iter().any(|choice| choice == value),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    fn supports_tree(&self, tree: &str) -> bool {
+        match (
+            self.version,
+            TREES.iter().find(|(name, _, _)| *name == tree),
+        ) {
+            (Some(version), Some((_, since, until))) => {
+                version >= *since && !until.is_some_and(|until| version >= until)
+            }
+            // Unknown versions and custom tree files are left to tedana.
+            _ => true,
+        }
+    }
+}
+
+fn parse_help(help: &str) -> BTreeMap<String, Option<Vec<String>>> {
+    let option_re = Regex::new(r"(--[A-Za-z][\w-]*)(?:[ =]\{([^}]*)\})?").unwrap();
+    let mut options = BTreeMap::new();
+    for captures in option_re.captures_iter(help) {
+        let flag = captures[1].to_string();
+        let choices = captures.get(2).map(|choices| {
+            choices
+                .as_str()
+                .split(',')
+                .map(|choice| choice.trim().to_string())
+                .collect::<Vec<_>>()
+        });
+        let entry = options.entry(flag).or_insert(None);
+        if choices.is_some() {
+            *entry = choices;
+        }
+    }
+    options
+}
+
+/// Options that only affect speed or presentation and can be dropped when the
+/// installed tedana doesn't know them.
+const OPTIONAL_FLAGS: &[&str] = &["--n-threads", "--png-cmap", "--no-reports", "--lowmem"];
+
+/// Adapts `args` to the installed tedana. Options tedana doesn't know are
+/// dropped when they are cosmetic and rejected otherwise; renamed trees are
+/// translated. Returns the adapted arguments together with warnings, or the
+/// errors that block the run.
+pub fn gate_args(
+    args: &TedanaArgs,
+    capabilities: &TedanaCapabilities,
+) -> Result<(TedanaArgs, Vec<ConfigIssue>), Vec<ConfigIssue>> {
+    let mut gated = args.clone();
+    let mut warnings = Vec::new();
+    let mut errors = Vec::new();
+    let version = &capabilities.version_string;
+
+    let mut issue = |field: &str, severity: Severity, message: String| {
+        let issue = ConfigIssue {
+            field: field.to_string(),
+            severity,
+            message,
+        };
+        match severity {
+            Severity::Error => errors.push(issue),
+            Severity::Warning => warnings.push(issue),
+        }
+    };
+
+    for (flag, field, values) in option_values(&gated) {
+        if values.is_empty() {
+            continue;
+        }
+        if !capabilities.supports(flag) {
+            if OPTIONAL_FLAGS.contains(&flag) {
+                issue(
+                    field,
+                    Severity::Warning,
+                    format!("tedana {} has no {} option; it was left out", version, flag),
+                );
+                clear_option(&mut gated, flag);
+            } else {
+                issue(
+                    field,
+                    Severity::Error,
+                    format!("tedana {} does not support {}", version, flag),
+                );
+            }
+        } else if let Some(value) = values
+            .iter()
+            .find(|value| !capabilities.allows(flag, value))
+        {
+            issue(
+                field,
+                Severity::Error,
+                format!(
+                    "tedana {} does not accept '{}' for {}",
+                    version, value, flag
+                ),
+            );
+        }
+    }
+
+    if let Some(tree) = gated.tree.clone().filter(|tree| !tree.trim().is_empty()) {
+        if capabilities.supports("--tree") && !capabilities.supports_tree(&tree) {
+            let alias = TREE_ALIASES
+                .iter()
+                .find_map(|(newer, older)| {
+                    if *newer == tree {
+                        Some(*older)
+                    } else if *older == tree {
+                        Some(*newer)
+                    } else {
+                        None
+                    }
+                })
+                .filter(|alias| capabilities.supports_tree(alias));
+            match alias {
+                Some(alias) => {
+                    issue(
+                        "tree",
+                        Severity::Warning,
+                        format!(
+                            "tedana {} calls the '{}' tree '{}'; using that instead",
+                            version, tree, alias
+                        ),
+                    );
+                    gated.tree = Some(alias.to_string());
+                }
+                None => issue(
+                    "tree",
+                    Severity::Error,
+                    format!("tedana {} has no built-in '{}' tree", version, tree),
+                ),
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok((gated, warnings))
+    } else {
+        Err(errors)
+    }
+}
+
+/// The flag, UI field name and current values of every option `TedanaArgs`
+/// would emit. Options that are unset have no values.
+fn option_values(args: &TedanaArgs) -> Vec<(&'static str, &'static str, Vec<String>)> {
+    let text = |values: &[String]| {
+        values
+            .iter()
+            .filter(|v| !v.trim().is_empty())
+            .cloned()
+            .collect()
+    };
+    let number = |value: Option<i64>| value.iter().map(|v| v.to_string()).collect();
+    // Switches have no value of their own, so they are reported as "".
+    let flag = |enabled: bool| enabled.then(String::new).into_iter().collect();
+    vec![
+        ("--out-dir", "outDir", text(args.out_dir.as_slice())),
+        ("--mask", "mask", text(args.mask.as_slice())),
+        ("--prefix", "prefix", text(args.prefix.as_slice())),
+        (
+            "--convention",
+            "convention",
+            text(args.convention.as_slice()),
+        ),
+        ("--masktype", "maskType", text(&args.mask_type)),
+        ("--fittype", "fitType", text(args.fit_type.as_slice())),
+        ("--combmode", "combMode", text(args.comb_mode.as_slice())),
+        ("--tedpca", "tedpca", text(args.tedpca.as_slice())),
+        ("--tree", "tree", text(args.tree.as_slice())),
+        ("--gscontrol", "gscontrol", text(args.gscontrol.as_slice())),
+        ("--png-cmap", "pngCmap", text(args.png_cmap.as_slice())),
+        ("--t2smap", "t2smap", text(args.t2smap.as_slice())),
+        ("--mix", "mix", text(args.mix.as_slice())),
+        ("--seed", "seed", number(args.seed)),
+        ("--maxit", "maxit", number(args.maxit.map(i64::from))),
+        (
+            "--maxrestart",
+            "maxrestart",
+            number(args.maxrestart.map(i64::from)),
+        ),
+        (
+            "--n-threads",
+            "nThreads",
+            number(args.n_threads.map(i64::from)),
+        ),
+        ("--tedort", "tedort", flag(args.tedort)),
+        ("--no-reports", "noReports", flag(args.no_reports)),
+        ("--verbose", "verbose", flag(args.verbose)),
+        ("--lowmem", "lowmem", flag(args.lowmem)),
+        ("--debug", "debug", flag(args.debug)),
+        ("--overwrite", "overwrite", flag(args.overwrite)),
+    ]
+}
+
+fn clear_option(args: &mut TedanaArgs, flag: &str) {
+    match flag {
+        "--n-threads" => args.n_threads = None,
+        "--png-cmap" => args.png_cmap = None,
+        "--no-reports" => args.no_reports = false,
+        "--lowmem" => args.lowmem = false,
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELP: &str = "\
+usage: tedana [-h] -d FILE [FILE ...] -e TE [TE ...] [--out-dir PATH]
+              [--fittype {loglin,curvefit}] [--tree TREE] [--n-threads N]
+
+options:
+  --out-dir PATH        Output directory.
+  --masktype {dropout,decay,none} [{dropout,decay,none} ...]
+  --combmode={t2s}      Combination scheme for TEs.
+  --tree TREE           Decision tree to use.
+  --verbose             Generate intermediate and additional files.
+";
+
+    fn capabilities(version: &str) -> TedanaCapabilities {
+        TedanaCapabilities {
+            version: TedanaVersion::parse(version),
+            version_string: version.to_string(),
+            options: parse_help(HELP),
+        }
+    }
+
+    fn choices(values: &[&str]) -> Option<Vec<String>> {
+        Some(values.iter().map(|v| v.to_string()).collect())
+    }
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!(
+            TedanaVersion::parse("24.0.1"),
+            Some(TedanaVersion(24, 0, 1))
+        );
+        assert_eq!(TedanaVersion::parse("v23.0"), Some(TedanaVersion(23, 0, 0)));
+        assert_eq!(
+            TedanaVersion::parse("24.0.2rc1+g1234"),
+            Some(TedanaVersion(24, 0, 2))
+        );
+        assert_eq!(TedanaVersion::parse("unknown"), None);
+    }
+
+    #[test]
+    fn parses_help_options_and_choices() {
+        let options = parse_help(HELP);
+        assert_eq!(options["--out-dir"], None);
+        assert_eq!(options["--fittype"], choices(&["loglin", "curvefit"]));
+        assert_eq!(
+            options["--masktype"],
+            choices(&["dropout", "decay", "none"])
+        );
+        assert_eq!(options["--combmode"], choices(&["t2s"]));
+        assert_eq!(options["--tree"], None);
+        assert!(options.contains_key("--verbose"));
+        assert!(options.contains_key("--n-threads"));
+        assert!(!options.contains_key("--lowmem"));
+    }
+
+    #[test]
+    fn choices_from_usage_line_are_not_lost_to_later_mentions() {
+        let options = parse_help("[--fittype {loglin,curvefit}]\n  --fittype FIT  Fit.\n");
+        assert_eq!(options["--fittype"], choices(&["loglin", "curvefit"]));
+    }
+
+    #[test]
+    fn gates_trees_by_version() {
+        let old = capabilities("23.0.2");
+        assert!(old.supports_tree("kundu"));
+        assert!(old.supports_tree("minimal"));
+        assert!(!old.supports_tree("tedana_orig"));
+        assert!(!old.supports_tree("meica"));
+
+        let new = capabilities("24.0.1");
+        assert!(!new.supports_tree("kundu"));
+        assert!(new.supports_tree("tedana_orig"));
+        assert!(new.supports_tree("meica"));
+
+        // Custom tree files and unknown versions are left to tedana.
+        assert!(new.supports_tree("/trees/custom.json"));
+        assert!(capabilities("dev").supports_tree("meica"));
+    }
+
+    #[test]
+    fn translates_renamed_trees() {
+        let args = TedanaArgs {
+            tree: Some("kundu".to_string()),
+            ..Default::default()
+        };
+        let (gated, warnings) = gate_args(&args, &capabilities("24.0.1")).unwrap();
+        assert_eq!(gated.tree.as_deref(), Some("tedana_orig"));
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].field, "tree");
+
+        let args = TedanaArgs {
+            tree: Some("tedana_orig".to_string()),
+            ..Default::default()
+        };
+        let (gated, _) = gate_args(&args, &capabilities("23.0.2")).unwrap();
+        assert_eq!(gated.tree.as_deref(), Some("kundu"));
+    }
+
+    #[test]
+    fn rejects_trees_without_an_alias() {
+        let args = TedanaArgs {
+            tree: Some("meica".to_string()),
+            ..Default::default()
+        };
+        let errors = gate_args(&args, &capabilities("23.0.2")).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, "tree");
+        assert!(matches!(errors[0].severity, Severity::Error));
+    }
+
+    #[test]
+    fn drops_optional_flags_and_rejects_unknown_ones() {
+        let args = TedanaArgs {
+            lowmem: true,
+            n_threads: Some(4),
+            ..Default::default()
+        };
+        let (gated, warnings) = gate_args(&args, &capabilities("24.0.1")).unwrap();
+        assert!(!gated.lowmem);
+        assert_eq!(gated.n_threads, Some(4));
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].field, "lowmem");
+
+        let args = TedanaArgs {
+            gscontrol: Some("gsr".to_string()),
+            ..Default::default()
+        };
+        let errors = gate_args(&args, &capabilities("24.0.1")).unwrap_err();
+        assert_eq!(errors[0].field, "gscontrol");
+    }
+
+    #[test]
+    fn rejects_values_outside_the_listed_choices() {
+        let args = TedanaArgs {
+            fit_type: Some("curvefit".to_string()),
+            mask_type: vec!["dropout".to_string(), "magic".to_string()],
+            ..Default::default()
+        };
+        let errors = gate_args(&args, &capabilities("24.0.1")).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, "maskType");
+    }
+}
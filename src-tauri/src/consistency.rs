@@ -0,0 +1,452 @@
+use crate::bids::{BidsStructure, EchoNum, FunctionalRun, RunRef};
+use crate::nifti::{self, NiftiHeader};
+use crate::tedana::Severity;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Why a run's echoes can't be processed together, or look suspicious.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasonCode {
+    TooFewEchoes,
+    MissingEcho,
+    DuplicateEcho,
+    EchoOrder,
+    UnreadableHeader,
+    MatrixMismatch,
+    VolumeMismatch,
+    VoxelSizeMismatch,
+    OrientationMismatch,
+    RepetitionTimeMismatch,
+    MissingEchoTime,
+    EchoTimesNotIncreasing,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ConsistencyIssue {
+    pub code: ReasonCode,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// The consistency report of one functional run.
+#[derive(Debug, Serialize, Clone)]
+pub struct RunConsistency {
+    #[serde(flatten)]
+    pub run: RunRef,
+    pub issues: Vec<ConsistencyIssue>,
+}
+
+// Tolerances for values that converters round differently per echo.
+const VOXEL_SIZE_TOLERANCE: f64 = 1e-4;
+const AFFINE_TOLERANCE: f64 = 1e-3;
+const TR_TOLERANCE: f64 = 1e-4;
+
+/// Checks every run in the dataset.
+pub fn check_structure(structure: &BidsStructure) -> Vec<RunConsistency> {
+    let mut reports = Vec::new();
+    for subject in &structure.subjects {
+        for session in &subject.sessions {
+            for run in &session.runs {
+                reports.push(RunConsistency {
+                    run: RunRef {
+                        subject: subject.name.clone(),
+                        session: session.name.clone(),
+                        run: run.key.clone(),
+                    },
+                    issues: check_run(run),
+                });
+            }
+        }
+    }
+    reports
+}
+
+/// Checks a run using the headers and sidecar metadata already collected
+/// for it.
+pub fn check_run(run: &FunctionalRun) -> Vec<ConsistencyIssue> {
+    check_echoes(&run.echo_nifti_file_paths, &run.headers, &run.echo_times())
+}
+
+/// Checks echo files given directly, e.g. from a queued job's arguments.
+/// Headers are read from disk.
+pub fn check_echo_files(files: &[String], echo_times: &[f64]) -> Vec<ConsistencyIssue> {
+    let headers: Vec<Option<NiftiHeader>> = files
+        .iter()
+        .map(|file| nifti::read_header(Path::new(file)).ok())
+        .collect();
+    let echo_times: Vec<Option<f64>> = (0..files.len())
+        .map(|index| echo_times.get(index).copied())
+        .collect();
+    check_echoes(files, &headers, &echo_times)
+}
+
+fn check_echoes(
+    files: &[String],
+    headers: &[Option<NiftiHeader>],
+    echo_times: &[Option<f64>],
+) -> Vec<ConsistencyIssue> {
+    let mut issues = Vec::new();
+    let mut issue = |code: ReasonCode, severity: Severity, message: String| {
+        issues.push(ConsistencyIssue {
+            code,
+            severity,
+            message,
+        })
+    };
+
+    if files.len() < 2 {
+        issue(
+            ReasonCode::TooFewEchoes,
+            Severity::Error,
+            format!("tedana needs at least two echoes, found {}", files.len()),
+        );
+    }
+
+    check_echo_numbers(files, &mut issue);
+
+    let mut reference: Option<(&str, &NiftiHeader)> = None;
+    for (file, header) in files.iter().map(|file| file_name(file)).zip(headers) {
+        let Some(header) = header else {
+            issue(
+                ReasonCode::UnreadableHeader,
+                Severity::Error,
+                format!("{} could not be read as a NIfTI image", file),
+            );
+            continue;
+        };
+        let Some((first_file, first)) = reference else {
+            reference = Some((file, header));
+            continue;
+        };
+
+        if header
+            .dimensions
+            .iter()
+            .take(3)
+            .ne(first.dimensions.iter().take(3))
+        {
+            issue(
+                ReasonCode::MatrixMismatch,
+                Severity::Error,
+                format!(
+                    "{} has a {:?} matrix but {} has {:?}",
+                    file,
+                    &header.dimensions[..header.dimensions.len().min(3)],
+                    first_file,
+                    &first.dimensions[..first.dimensions.len().min(3)]
+                ),
+            );
+        }
+        if header.volumes != first.volumes {
+            issue(
+                ReasonCode::VolumeMismatch,
+                Severity::Error,
+                format!(
+                    "{} has {} volumes but {} has {}",
+                    file, header.volumes, first_file, first.volumes
+                ),
+            );
+        }
+        if header.voxel_sizes.len() != first.voxel_sizes.len()
+            || header
+                .voxel_sizes
+                .iter()
+                .zip(&first.voxel_sizes)
+                .any(|(a, b)| (a - b).abs() > VOXEL_SIZE_TOLERANCE)
+        {
+            issue(
+                ReasonCode::VoxelSizeMismatch,
+                Severity::Error,
+                format!(
+                    "{} has {:?} voxels but {} has {:?}",
+                    file, header.voxel_sizes, first_file, first.voxel_sizes
+                ),
+            );
+        }
+        if header
+            .affine
+            .iter()
+            .flatten()
+            .zip(first.affine.iter().flatten())
+            .any(|(a, b)| (a - b).abs() > AFFINE_TOLERANCE)
+        {
+            issue(
+                ReasonCode::OrientationMismatch,
+                Severity::Error,
+                format!(
+                    "{} is not aligned with {}: their affines differ",
+                    file, first_file
+                ),
+            );
+        }
+        let describe = |tr: Option<f64>| match tr {
+            Some(tr) => format!("a repetition time of {} s", tr),
+            None => "no repetition time".to_string(),
+        };
+        let tr_differs = match (header.repetition_time, first.repetition_time) {
+            (Some(a), Some(b)) => (a - b).abs() > TR_TOLERANCE,
+            (a, b) => a.is_some() != b.is_some(),
+        };
+        if tr_differs {
+            issue(
+                ReasonCode::RepetitionTimeMismatch,
+                Severity::Warning,
+                format!(
+                    "{} has {} but {} has {}",
+                    file,
+                    describe(header.repetition_time),
+                    first_file,
+                    describe(first.repetition_time)
+                ),
+            );
+        }
+    }
+
+    let missing: Vec<&str> = files
+        .iter()
+        .zip(echo_times)
+        .filter(|(_, time)| time.is_none())
+        .map(|(file, _)| file_name(file))
+        .collect();
+    if !missing.is_empty() {
+        for file in missing {
+            issue(
+                ReasonCode::MissingEchoTime,
+                Severity::Warning,
+                format!("No echo time was found for {}", file),
+            );
+        }
+    } else if echo_times.windows(2).any(|pair| pair[0] >= pair[1]) {
+        issue(
+            ReasonCode::EchoTimesNotIncreasing,
+            Severity::Error,
+            format!(
+                "Echo times are not strictly increasing in echo order: {:?}",
+                echo_times.iter().flatten().collect::<Vec<_>>()
+            ),
+        );
+    }
+
+    issues
+}
+
+/// Looks for gaps, repeats and ordering problems in the files' echo entities.
+/// Files without one, e.g. outside a BIDS dataset, are not checked.
+fn check_echo_numbers(files: &[String], issue: &mut impl FnMut(ReasonCode, Severity, String)) {
+    let Some(echoes) = files
+        .iter()
+        .map(|file| EchoNum::from_path(Path::new(file)).map(EchoNum::get))
+        .collect::<Option<Vec<u8>>>()
+    else {
+        return;
+    };
+
+    let mut counts: BTreeMap<u8, usize> = BTreeMap::new();
+    for echo in &echoes {
+        *counts.entry(*echo).or_default() += 1;
+    }
+    for (echo, count) in counts.iter().filter(|(_, count)| **count > 1) {
+        issue(
+            ReasonCode::DuplicateEcho,
+            Severity::Error,
+            format!("Echo {} appears {} times", echo, count),
+        );
+    }
+    let sorted: Vec<u8> = counts.into_keys().collect();
+
+    let missing: Vec<u8> = (1..=sorted.last().copied().unwrap_or(0))
+        .filter(|echo| !sorted.contains(echo))
+        .collect();
+    if !missing.is_empty() {
+        issue(
+            ReasonCode::MissingEcho,
+            Severity::Error,
+            format!(
+                "Echoes {:?} are missing; only echoes {:?} were found",
+                missing, sorted
+            ),
+        );
+    }
+
+    if echoes.windows(2).any(|pair| pair[0] > pair[1]) {
+        issue(
+            ReasonCode::EchoOrder,
+            Severity::Error,
+            format!(
+                "Data files are not in echo order ({:?}); echo times would be matched to the wrong files",
+                echoes
+            ),
+        );
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> NiftiHeader {
+        NiftiHeader {
+            version: 1,
+            dimensions: vec![64, 64, 32, 200],
+            voxel_sizes: vec![3.0, 3.0, 3.0],
+            volumes: 200,
+            repetition_time: Some(2.0),
+            datatype: "float32".to_string(),
+            datatype_code: 16,
+            qform_code: 1,
+            sform_code: 1,
+            affine: [
+                [3.0, 0.0, 0.0, -90.0],
+                [0.0, 3.0, 0.0, -126.0],
+                [0.0, 0.0, 3.0, -72.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    fn files(echoes: &[u8]) -> Vec<String> {
+        echoes
+            .iter()
+            .map(|echo| {
+                format!(
+                    "/data/sub-01/func/sub-01_task-rest_echo-{}_bold.nii.gz",
+                    echo
+                )
+            })
+            .collect()
+    }
+
+    fn codes(issues: &[ConsistencyIssue]) -> Vec<ReasonCode> {
+        issues.iter().map(|issue| issue.code).collect()
+    }
+
+    fn check(
+        echoes: &[u8],
+        headers: Vec<NiftiHeader>,
+        echo_times: &[f64],
+    ) -> Vec<ConsistencyIssue> {
+        let headers: Vec<Option<NiftiHeader>> = headers.into_iter().map(Some).collect();
+        let echo_times: Vec<Option<f64>> = echo_times.iter().copied().map(Some).collect();
+        check_echoes(&files(echoes), &headers, &echo_times)
+    }
+
+    fn numbers(echoes: &[u8]) -> Vec<ConsistencyIssue> {
+        let mut issues = Vec::new();
+        check_echo_numbers(&files(echoes), &mut |code, severity, message| {
+            issues.push(ConsistencyIssue {
+                code,
+                severity,
+                message,
+            })
+        });
+        issues
+    }
+
+    #[test]
+    fn accepts_consistent_echoes() {
+        let issues = check(&[1, 2, 3], vec![header(); 3], &[0.014, 0.039, 0.064]);
+        assert!(issues.is_empty(), "{:?}", issues);
+    }
+
+    #[test]
+    fn reports_gaps() {
+        let issues = numbers(&[1, 2, 4]);
+        assert_eq!(codes(&issues), [ReasonCode::MissingEcho]);
+        assert!(issues[0].message.contains("[3]"));
+    }
+
+    #[test]
+    fn reports_each_duplicate_echo_once() {
+        let issues = numbers(&[1, 2, 2, 2, 3, 3]);
+        assert_eq!(
+            codes(&issues),
+            [ReasonCode::DuplicateEcho, ReasonCode::DuplicateEcho]
+        );
+        assert_eq!(issues[0].message, "Echo 2 appears 3 times");
+        assert_eq!(issues[1].message, "Echo 3 appears 2 times");
+    }
+
+    #[test]
+    fn reports_files_out_of_echo_order() {
+        assert_eq!(codes(&numbers(&[2, 1, 3])), [ReasonCode::EchoOrder]);
+    }
+
+    #[test]
+    fn skips_files_without_echo_entities() {
+        let files = vec!["/tmp/a.nii.gz".to_string(), "/tmp/b.nii.gz".to_string()];
+        let mut issues = 0;
+        check_echo_numbers(&files, &mut |_, _, _| issues += 1);
+        assert_eq!(issues, 0);
+    }
+
+    #[test]
+    fn needs_two_echoes() {
+        let issues = check(&[1], vec![header()], &[0.014]);
+        assert_eq!(codes(&issues), [ReasonCode::TooFewEchoes]);
+    }
+
+    #[test]
+    fn tolerates_rounding_between_echoes() {
+        let mut second = header();
+        second.voxel_sizes[0] += VOXEL_SIZE_TOLERANCE / 2.0;
+        second.affine[0][3] += AFFINE_TOLERANCE / 2.0;
+        second.repetition_time = Some(2.0 + TR_TOLERANCE / 2.0);
+        let issues = check(&[1, 2], vec![header(), second], &[0.014, 0.039]);
+        assert!(issues.is_empty(), "{:?}", issues);
+    }
+
+    #[test]
+    fn reports_differences_beyond_the_tolerances() {
+        let mut second = header();
+        second.voxel_sizes[0] += VOXEL_SIZE_TOLERANCE * 10.0;
+        second.affine[0][3] += AFFINE_TOLERANCE * 10.0;
+        second.repetition_time = Some(2.5);
+        second.volumes = 199;
+        second.dimensions = vec![64, 64, 30, 199];
+        let issues = check(&[1, 2], vec![header(), second], &[0.014, 0.039]);
+        assert_eq!(
+            codes(&issues),
+            [
+                ReasonCode::MatrixMismatch,
+                ReasonCode::VolumeMismatch,
+                ReasonCode::VoxelSizeMismatch,
+                ReasonCode::OrientationMismatch,
+                ReasonCode::RepetitionTimeMismatch,
+            ]
+        );
+        assert_eq!(issues[4].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn reports_unreadable_headers() {
+        let issues = check_echoes(
+            &files(&[1, 2]),
+            &[Some(header()), None],
+            &[Some(0.014), Some(0.039)],
+        );
+        assert_eq!(codes(&issues), [ReasonCode::UnreadableHeader]);
+    }
+
+    #[test]
+    fn checks_echo_times() {
+        let issues = check(&[1, 2, 3], vec![header(); 3], &[0.014, 0.039, 0.039]);
+        assert_eq!(codes(&issues), [ReasonCode::EchoTimesNotIncreasing]);
+
+        let issues = check_echoes(
+            &files(&[1, 2]),
+            &[Some(header()), Some(header())],
+            &[Some(0.014), None],
+        );
+        assert_eq!(codes(&issues), [ReasonCode::MissingEchoTime]);
+        assert_eq!(issues[0].severity, Severity::Warning);
+    }
+}
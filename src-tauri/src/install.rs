@@ -0,0 +1,201 @@
+use crate::python_env::PythonEnvironment;
+use crate::tedana;
+use serde::Serialize;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tauri::Window;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallStage {
+    CreatingEnvironment,
+    InstallingPackages,
+    Verifying,
+    Done,
+}
+
+/// Progress of an offline environment setup, emitted as `tedana-install-output`.
+#[derive(Debug, Serialize, Clone)]
+pub struct InstallProgress {
+    pub stage: InstallStage,
+    pub line: String,
+    pub is_error: bool,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct InstalledEnvironment {
+    pub python_path: String,
+    pub tedana_version: String,
+}
+
+/// Creates a virtual environment at `env_path` with `base_python` and
+/// installs tedana into it from a local wheel directory, without touching
+/// the network.
+pub fn create_offline_environment(
+    window: &Window,
+    base_python: &str,
+    env_path: &str,
+    wheelhouse: &str,
+) -> Result<InstalledEnvironment, String> {
+    let env_dir = Path::new(env_path);
+    let wheel_dir = Path::new(wheelhouse);
+    check_wheelhouse(wheel_dir)?;
+    if env_dir.exists()
+        && fs::read_dir(env_dir)
+            .map_err(|e| format!("Failed to read {:?}: {}", env_dir, e))?
+            .next()
+            .is_some()
+    {
+        return Err(format!("{:?} already exists and is not empty", env_dir));
+    }
+
+    let base = PythonEnvironment::detect(base_python, None)?;
+    let mut venv = base.python_command();
+    venv.arg("-m").arg("venv").arg(env_dir);
+    run_streaming(window, InstallStage::CreatingEnvironment, venv)?;
+
+    let env_python = environment_python(env_dir);
+    let environment = PythonEnvironment::detect(&env_python.to_string_lossy(), Some(env_path))?;
+
+    // Upgrade pip first when the wheelhouse ships one, since the copy bundled
+    // with older interpreters may not understand newer wheel tags.
+    if has_wheel(wheel_dir, "pip") {
+        let mut pip = environment.python_command();
+        pip.args([
+            "-m",
+            "pip",
+            "install",
+            "--no-index",
+            "--upgrade",
+            "--find-links",
+        ])
+        .arg(wheel_dir)
+        .arg("pip");
+        run_streaming(window, InstallStage::InstallingPackages, pip)?;
+    }
+
+    let mut install = environment.python_command();
+    install
+        .args(["-m", "pip", "install", "--no-index", "--find-links"])
+        .arg(wheel_dir)
+        .arg("tedana");
+    run_streaming(window, InstallStage::InstallingPackages, install)?;
+
+    emit_progress(
+        window,
+        InstallStage::Verifying,
+        "Checking the tedana installation".to_string(),
+        false,
+    );
+    let python_path = env_python.to_string_lossy().into_owned();
+    let tedana_version =
+        tedana::check_tedana_installation(python_path.clone(), Some(env_path.to_string()))?;
+
+    emit_progress(
+        window,
+        InstallStage::Done,
+        format!("Installed tedana {} into {}", tedana_version, env_path),
+        false,
+    );
+    Ok(InstalledEnvironment {
+        python_path,
+        tedana_version,
+    })
+}
+
+fn check_wheelhouse(wheel_dir: &Path) -> Result<(), String> {
+    if !wheel_dir.is_dir() {
+        return Err(format!(
+            "The wheel directory {:?} does not exist",
+            wheel_dir
+        ));
+    }
+    if !has_wheel(wheel_dir, "tedana") {
+        return Err(format!(
+            "No tedana wheel or source archive found in {:?}",
+            wheel_dir
+        ));
+    }
+    Ok(())
+}
+
+/// Whether the directory holds a distribution of `package`, either as a
+/// wheel or as a source archive.
+fn has_wheel(wheel_dir: &Path, package: &str) -> bool {
+    let prefix = format!("{}-", package.to_lowercase());
+    fs::read_dir(wheel_dir)
+        .map(|entries| {
+            entries.filter_map(|entry| entry.ok()).any(|entry| {
+                let name = entry.file_name().to_string_lossy().to_lowercase();
+                name.starts_with(&prefix) && (name.ends_with(".whl") || name.ends_with(".tar.gz"))
+            })
+        })
+        .unwrap_or(false)
+}
+
+fn environment_python(env_dir: &Path) -> PathBuf {
+    if cfg!(windows) {
+        env_dir.join("Scripts").join("python.exe")
+    } else {
+        env_dir.join("bin").join("python")
+    }
+}
+
+fn emit_progress(window: &Window, stage: InstallStage, line: String, is_error: bool) {
+    window
+        .emit(
+            "tedana-install-output",
+            InstallProgress {
+                stage,
+                line,
+                is_error,
+            },
+        )
+        .unwrap();
+}
+
+fn run_streaming(window: &Window, stage: InstallStage, mut command: Command) -> Result<(), String> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to start process: {}", e))?;
+
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+
+    let window_clone = window.clone();
+    let stdout_thread = std::thread::spawn(move || {
+        let reader = BufReader::new(stdout);
+        for line in reader.lines().map_while(Result::ok) {
+            emit_progress(&window_clone, stage, line, false);
+        }
+    });
+
+    let window_clone = window.clone();
+    let stderr_thread = std::thread::spawn(move || {
+        let reader = BufReader::new(stderr);
+        for line in reader.lines().map_while(Result::ok) {
+            emit_progress(&window_clone, stage, line, true);
+        }
+    });
+
+    let status = child
+        .wait()
+        .map_err(|e| format!("Failed to wait on child: {}", e))?;
+    let _ = stdout_thread.join();
+    let _ = stderr_thread.join();
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{:?} failed with {}",
+            command.get_program(),
+            status
+        ))
+    }
+}
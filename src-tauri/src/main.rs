@@ -3,13 +3,40 @@
 
 use tauri::Manager;
 
+mod args;
 mod bids;
+mod bids_dataset;
+mod bids_entities;
+mod bids_error;
+mod bids_index;
+mod bids_tables;
+mod bids_watch;
+mod capabilities;
+mod consistency;
+mod install;
+mod nifti;
+mod progress;
+mod python_env;
+mod queue;
+mod run_filter;
 mod tedana;
 mod theme;
-use bids::BidsStructure;
+mod validation;
+use args::TedanaArgs;
+use bids::{BidsStructure, DerivativeQuery, RunRef};
+use bids_error::BidsError;
+use capabilities::TedanaCapabilities;
+use consistency::RunConsistency;
+use install::InstalledEnvironment;
+use nifti::NiftiHeader;
+use python_env::{DiscoveredEnvironment, PythonEnvironment};
+use queue::{Job, NewJob};
 use std::fs;
+use std::path::{Path, PathBuf};
 use tauri::http::header::HeaderValue;
 use tauri::http::Response;
+use tedana::{ConfigIssue, TedanaCommand};
+use validation::ValidationReport;
 
 #[tauri::command]
 async fn read_html_file(path: String) -> Result<String, String> {
@@ -34,30 +61,235 @@ fn check_tedana_installation(
     tedana::check_tedana_installation(python_path, environment_path)
 }
 
+#[tauri::command]
+fn detect_python_environment(
+    python_path: String,
+    environment_path: Option<String>,
+) -> Result<PythonEnvironment, String> {
+    PythonEnvironment::detect(&python_path, environment_path.as_deref())
+}
+
+#[tauri::command]
+async fn discover_python_environments(
+    project_dirs: Option<Vec<String>>,
+) -> Vec<DiscoveredEnvironment> {
+    let project_dirs = project_dirs
+        .unwrap_or_default()
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    python_env::discover_environments(project_dirs).await
+}
+
+#[tauri::command]
+async fn create_offline_environment(
+    window: tauri::Window,
+    base_python: String,
+    env_path: String,
+    wheelhouse: String,
+) -> Result<InstalledEnvironment, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        install::create_offline_environment(&window, &base_python, &env_path, &wheelhouse)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
 #[tauri::command]
 async fn run_tedana_command(
     window: tauri::Window,
     python_path: String,
-    command_args: String,
+    args: Option<TedanaArgs>,
+    command_args: Option<String>,
 ) -> Result<String, String> {
-    tedana::run_tedana(window, python_path, command_args).await
+    let command = match (args, command_args) {
+        (Some(args), _) => TedanaCommand::Args(Box::new(args)),
+        #[allow(deprecated)]
+        (None, Some(command_args)) => TedanaCommand::Shell(command_args),
+        (None, None) => return Err("No tedana arguments were provided".to_string()),
+    };
+    let job_id = queue::allocate_job_id().await;
+    tedana::run_tedana(window, job_id, python_path, command).await
+}
+
+#[tauri::command]
+async fn kill_tedana_command(job_id: Option<u64>) -> Result<(), String> {
+    match job_id {
+        Some(job_id) => tedana::kill_tedana(job_id).await,
+        None => tedana::kill_all_tedana().await,
+    }
+}
+
+#[tauri::command]
+async fn validate_tedana_config(
+    args: TedanaArgs,
+    bids_path: String,
+    convention: String,
+    derivatives: Option<DerivativeQuery>,
+    python_path: Option<String>,
+) -> Result<Vec<ConfigIssue>, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        tedana::validate_tedana_config(
+            &args,
+            &bids_path,
+            &convention,
+            derivatives.as_ref(),
+            python_path.as_deref(),
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn validate_tedana_configs(
+    args: Vec<TedanaArgs>,
+    bids_path: String,
+    convention: String,
+    derivatives: Option<DerivativeQuery>,
+    python_path: Option<String>,
+) -> Result<Vec<Vec<ConfigIssue>>, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        tedana::validate_tedana_configs(
+            &args,
+            &bids_path,
+            &convention,
+            derivatives.as_ref(),
+            python_path.as_deref(),
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn get_tedana_capabilities(python_path: String) -> Result<TedanaCapabilities, String> {
+    tauri::async_runtime::spawn_blocking(move || TedanaCapabilities::for_python(&python_path))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn enqueue_tedana_jobs(window: tauri::Window, jobs: Vec<NewJob>) -> Result<Vec<Job>, String> {
+    queue::enqueue_jobs(window, jobs).await
+}
+
+#[tauri::command]
+async fn list_tedana_jobs() -> Vec<Job> {
+    queue::list_jobs().await
+}
+
+#[tauri::command]
+async fn reorder_tedana_job(
+    window: tauri::Window,
+    job_id: u64,
+    new_index: usize,
+) -> Result<(), String> {
+    queue::reorder_job(window, job_id, new_index).await
 }
 
 #[tauri::command]
-async fn kill_tedana_command() -> Result<(), String> {
-    tedana::kill_tedana().await
+async fn cancel_tedana_job(window: tauri::Window, job_id: u64) -> Result<(), String> {
+    queue::cancel_job(window, job_id).await
 }
 
 #[tauri::command]
-fn validate_bids_directory(path: String, convention: String) -> Result<String, String> {
-    bids::validate_bids_directory(path, convention)
+async fn retry_tedana_job(window: tauri::Window, job_id: u64) -> Result<(), String> {
+    queue::retry_job(window, job_id).await
 }
 
 #[tauri::command]
-fn extract_bids_structure(path: String, convention: String) -> Result<BidsStructure, String> {
+async fn get_max_concurrent_jobs() -> Option<usize> {
+    queue::get_max_concurrent_jobs().await
+}
+
+#[tauri::command]
+async fn set_max_concurrent_jobs(
+    window: tauri::Window,
+    max_concurrent_jobs: Option<usize>,
+) -> Result<(), String> {
+    queue::set_max_concurrent_jobs(window, max_concurrent_jobs).await
+}
+
+#[tauri::command]
+fn validate_bids_directory(
+    path: String,
+    convention: String,
+) -> Result<ValidationReport, BidsError> {
+    validation::validate_bids_directory(&path, &convention)
+}
+
+#[tauri::command]
+fn extract_bids_structure(path: String, convention: String) -> Result<BidsStructure, BidsError> {
     bids::extract_bids_structure(&path, &convention)
 }
 
+/// Like `extract_bids_structure` or `extract_derivatives_structure`, but
+/// rereads every file instead of reusing the dataset's index.
+#[tauri::command]
+fn rescan_bids_structure(
+    path: String,
+    convention: String,
+    derivatives: Option<DerivativeQuery>,
+) -> Result<BidsStructure, BidsError> {
+    bids_index::clear(Path::new(&path));
+    match derivatives {
+        Some(query) => bids::extract_derivatives_structure(&path, &query),
+        None => bids::extract_bids_structure(&path, &convention),
+    }
+}
+
+#[tauri::command]
+fn watch_bids_dataset(
+    app: tauri::AppHandle,
+    path: String,
+    convention: String,
+    derivatives: Option<DerivativeQuery>,
+    structure: BidsStructure,
+) -> Result<u64, String> {
+    bids_watch::watch_dataset(app, path, convention, derivatives, structure)
+}
+
+#[tauri::command]
+fn unwatch_bids_dataset(watch_id: u64) {
+    bids_watch::unwatch_dataset(watch_id);
+}
+
+#[tauri::command]
+fn filter_bids_runs(structure: BidsStructure, filter: String) -> Result<Vec<RunRef>, String> {
+    run_filter::filter_runs(&structure, &filter)
+}
+
+#[tauri::command]
+fn extract_derivatives_structure(
+    path: String,
+    derivatives: DerivativeQuery,
+) -> Result<BidsStructure, BidsError> {
+    bids::extract_derivatives_structure(&path, &derivatives)
+}
+
+#[tauri::command]
+async fn check_echo_consistency(
+    path: String,
+    convention: String,
+    derivatives: Option<DerivativeQuery>,
+) -> Result<Vec<RunConsistency>, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let structure = match derivatives {
+            Some(query) => bids::extract_derivatives_structure(&path, &query)?,
+            None => bids::extract_bids_structure(&path, &convention)?,
+        };
+        Ok(consistency::check_structure(&structure))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+fn read_nifti_header(path: String) -> Result<NiftiHeader, String> {
+    nifti::read_header(&PathBuf::from(path))
+}
+
 fn main() {
     tauri::Builder::default()
         .setup(|app| {
@@ -70,6 +302,12 @@ fn main() {
             tauri::async_runtime::spawn(async move {
                 listen_system_theme_changes(app_handle).await;
             });
+            let main_window = app.get_window("main").unwrap();
+            let data_dir = app.path_resolver().app_data_dir();
+            bids_index::init(data_dir.clone());
+            tauri::async_runtime::spawn(async move {
+                queue::run_job_queue(main_window, data_dir).await;
+            });
             Ok(())
         })
         .register_uri_scheme_protocol("tedana", move |_app, request| {
@@ -114,10 +352,30 @@ fn main() {
         .invoke_handler(tauri::generate_handler![
             get_system_theme,
             check_tedana_installation,
+            detect_python_environment,
+            discover_python_environments,
+            create_offline_environment,
             run_tedana_command,
             kill_tedana_command,
+            validate_tedana_config,
+            validate_tedana_configs,
+            get_tedana_capabilities,
+            enqueue_tedana_jobs,
+            list_tedana_jobs,
+            reorder_tedana_job,
+            cancel_tedana_job,
+            retry_tedana_job,
+            get_max_concurrent_jobs,
+            set_max_concurrent_jobs,
             validate_bids_directory,
             extract_bids_structure,
+            filter_bids_runs,
+            extract_derivatives_structure,
+            rescan_bids_structure,
+            watch_bids_dataset,
+            unwatch_bids_dataset,
+            check_echo_consistency,
+            read_nifti_header,
             read_html_file,
         ])
         .run(tauri::generate_context!())
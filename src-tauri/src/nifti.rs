@@ -0,0 +1,461 @@
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+const NIFTI1_HEADER_SIZE: i32 = 348;
+const NIFTI2_HEADER_SIZE: i32 = 540;
+
+/// The parts of a NIfTI-1 or NIfTI-2 header needed to check tedana inputs.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct NiftiHeader {
+    /// 1 or 2.
+    pub version: u8,
+    /// Size of each dimension, `dim[1..=dim[0]]`.
+    pub dimensions: Vec<u64>,
+    /// Spatial voxel size in the file's units, `pixdim[1..=3]`.
+    pub voxel_sizes: Vec<f64>,
+    /// Number of volumes, 1 for 3D images.
+    pub volumes: u64,
+    /// `pixdim[4]` converted to seconds, when the image has a time axis.
+    pub repetition_time: Option<f64>,
+    pub datatype: String,
+    pub datatype_code: i16,
+    pub qform_code: i32,
+    pub sform_code: i32,
+    /// Voxel-to-world transform from the sform when set, otherwise the qform,
+    /// otherwise the voxel sizes alone.
+    pub affine: [[f64; 4]; 4],
+}
+
+/// Reads the header of a `.nii` or `.nii.gz` file. Only the first few hundred
+/// bytes are decompressed, so this is cheap even for large runs.
+pub fn read_header(path: &Path) -> Result<NiftiHeader, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+    let gzipped = path.to_string_lossy().ends_with(".gz");
+    let reader: Box<dyn Read> = if gzipped {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+
+    let mut bytes = Vec::with_capacity(NIFTI2_HEADER_SIZE as usize);
+    reader
+        .take(NIFTI2_HEADER_SIZE as u64)
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Failed to read the header of {:?}: {}", path, e))?;
+    if bytes.len() < 4 {
+        return Err(format!("{:?} is too short to be a NIfTI file", path));
+    }
+
+    let header = HeaderBytes::detect(&bytes)
+        .ok_or_else(|| format!("{:?} does not have a NIfTI-1 or NIfTI-2 header", path))?;
+    if bytes.len() < header.size() {
+        return Err(format!("{:?} ends inside its NIfTI header", path));
+    }
+    if !header.has_magic() {
+        return Err(format!(
+            "{:?} has the size of a NIfTI-{} header but not its magic string",
+            path, header.version
+        ));
+    }
+    Ok(header.parse())
+}
+
+struct HeaderBytes<'a> {
+    bytes: &'a [u8],
+    big_endian: bool,
+    version: u8,
+}
+
+impl<'a> HeaderBytes<'a> {
+    /// Works out the version and byte order from `sizeof_hdr`, which is the
+    /// only field guaranteed to be in the same place in both versions.
+    fn detect(bytes: &'a [u8]) -> Option<Self> {
+        let raw: [u8; 4] = bytes[0..4].try_into().ok()?;
+        [false, true].into_iter().find_map(|big_endian| {
+            let size = if big_endian {
+                i32::from_be_bytes(raw)
+            } else {
+                i32::from_le_bytes(raw)
+            };
+            let version = match size {
+                NIFTI1_HEADER_SIZE => 1,
+                NIFTI2_HEADER_SIZE => 2,
+                _ => return None,
+            };
+            Some(HeaderBytes {
+                bytes,
+                big_endian,
+                version,
+            })
+        })
+    }
+
+    fn size(&self) -> usize {
+        if self.version == 1 {
+            NIFTI1_HEADER_SIZE as usize
+        } else {
+            NIFTI2_HEADER_SIZE as usize
+        }
+    }
+
+    /// `n+1`/`ni1` at byte 344 for NIfTI-1, `n+2`/`ni2` at byte 4 for
+    /// NIfTI-2, where `ni` marks a header stored apart from its image.
+    fn has_magic(&self) -> bool {
+        let (offset, versions): (usize, [&[u8]; 2]) = if self.version == 1 {
+            (344, [b"n+1\0", b"ni1\0"])
+        } else {
+            (4, [b"n+2\0", b"ni2\0"])
+        };
+        versions.contains(&&self.bytes[offset..offset + 4])
+    }
+
+    fn field<const N: usize>(&self, offset: usize) -> [u8; N] {
+        let mut raw: [u8; N] = self.bytes[offset..offset + N].try_into().unwrap();
+        // Swapping to little endian lets every reader below use from_le_bytes.
+        if self.big_endian {
+            raw.reverse();
+        }
+        raw
+    }
+
+    fn i16(&self, offset: usize) -> i16 {
+        i16::from_le_bytes(self.field(offset))
+    }
+
+    fn i32(&self, offset: usize) -> i32 {
+        i32::from_le_bytes(self.field(offset))
+    }
+
+    fn i64(&self, offset: usize) -> i64 {
+        i64::from_le_bytes(self.field(offset))
+    }
+
+    fn f32(&self, offset: usize) -> f64 {
+        f32::from_le_bytes(self.field(offset)) as f64
+    }
+
+    fn f64(&self, offset: usize) -> f64 {
+        f64::from_le_bytes(self.field(offset))
+    }
+
+    fn parse(&self) -> NiftiHeader {
+        // Every field differs in position, and most in width, between the
+        // two versions.
+        let (dim, pixdim): (Vec<i64>, Vec<f64>) = if self.version == 1 {
+            (
+                (0..8).map(|i| self.i16(40 + 2 * i) as i64).collect(),
+                (0..8).map(|i| self.f32(76 + 4 * i)).collect(),
+            )
+        } else {
+            (
+                (0..8).map(|i| self.i64(16 + 8 * i)).collect(),
+                (0..8).map(|i| self.f64(104 + 8 * i)).collect(),
+            )
+        };
+        let (datatype_code, qform_code, sform_code, xyzt_units) = if self.version == 1 {
+            (
+                self.i16(70),
+                self.i16(252) as i32,
+                self.i16(254) as i32,
+                self.bytes[123] as i32,
+            )
+        } else {
+            (self.i16(12), self.i32(344), self.i32(348), self.i32(500))
+        };
+        let float = |index: usize| {
+            if self.version == 1 {
+                self.f32(256 + 4 * index)
+            } else {
+                self.f64(352 + 8 * index)
+            }
+        };
+        // quatern_b, quatern_c, quatern_d, qoffset_x, qoffset_y, qoffset_z,
+        // then srow_x, srow_y and srow_z.
+        let quatern = [float(0), float(1), float(2)];
+        let qoffset = [float(3), float(4), float(5)];
+        let srow: Vec<f64> = (6..18).map(float).collect();
+
+        let ndim = dim[0].clamp(0, 7) as usize;
+        let dimensions: Vec<u64> = dim[1..=ndim].iter().map(|d| (*d).max(0) as u64).collect();
+        let voxel_sizes: Vec<f64> = pixdim[1..=ndim.min(3)].to_vec();
+        let volumes = if ndim >= 4 { dimensions[3].max(1) } else { 1 };
+        let repetition_time =
+            (ndim >= 4 && pixdim[4] > 0.0).then(|| pixdim[4] * time_unit_seconds(xyzt_units));
+
+        let affine = if sform_code > 0 {
+            [
+                [srow[0], srow[1], srow[2], srow[3]],
+                [srow[4], srow[5], srow[6], srow[7]],
+                [srow[8], srow[9], srow[10], srow[11]],
+                [0.0, 0.0, 0.0, 1.0],
+            ]
+        } else if qform_code > 0 {
+            qform_affine(quatern, qoffset, &pixdim)
+        } else {
+            [
+                [pixdim[1], 0.0, 0.0, 0.0],
+                [0.0, pixdim[2], 0.0, 0.0],
+                [0.0, 0.0, pixdim[3], 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ]
+        };
+
+        NiftiHeader {
+            version: self.version,
+            dimensions,
+            voxel_sizes,
+            volumes,
+            repetition_time,
+            datatype: datatype_name(datatype_code).to_string(),
+            datatype_code,
+            qform_code,
+            sform_code,
+            affine,
+        }
+    }
+}
+
+/// Builds the qform affine from the quaternion, offsets and voxel sizes, as
+/// described in the NIfTI-1 header documentation.
+fn qform_affine(quatern: [f64; 3], qoffset: [f64; 3], pixdim: &[f64]) -> [[f64; 4]; 4] {
+    let [b, c, d] = quatern;
+    let a = (1.0 - (b * b + c * c + d * d)).max(0.0).sqrt();
+    let qfac = if pixdim[0] < 0.0 { -1.0 } else { 1.0 };
+    let rotation = [
+        [
+            a * a + b * b - c * c - d * d,
+            2.0 * (b * c - a * d),
+            2.0 * (b * d + a * c),
+        ],
+        [
+            2.0 * (b * c + a * d),
+            a * a + c * c - b * b - d * d,
+            2.0 * (c * d - a * b),
+        ],
+        [
+            2.0 * (b * d - a * c),
+            2.0 * (c * d + a * b),
+            a * a + d * d - c * c - b * b,
+        ],
+    ];
+    let scale = [pixdim[1], pixdim[2], qfac * pixdim[3]];
+
+    let mut affine = [[0.0; 4]; 4];
+    for row in 0..3 {
+        for column in 0..3 {
+            affine[row][column] = rotation[row][column] * scale[column];
+        }
+        affine[row][3] = qoffset[row];
+    }
+    affine[3][3] = 1.0;
+    affine
+}
+
+/// Multiplier that converts `pixdim[4]` to seconds. Unknown units are taken
+/// as seconds, which is what most converters write.
+fn time_unit_seconds(xyzt_units: i32) -> f64 {
+    match xyzt_units & 0x38 {
+        16 => 1e-3,
+        24 => 1e-6,
+        _ => 1.0,
+    }
+}
+
+fn datatype_name(code: i16) -> &'static str {
+    match code {
+        1 => "binary",
+        2 => "uint8",
+        4 => "int16",
+        8 => "int32",
+        16 => "float32",
+        32 => "complex64",
+        64 => "float64",
+        128 => "rgb24",
+        256 => "int8",
+        512 => "uint16",
+        768 => "uint32",
+        1024 => "int64",
+        1280 => "uint64",
+        1536 => "float128",
+        1792 => "complex128",
+        2048 => "complex256",
+        2304 => "rgba32",
+        _ => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    /// Fills in a header buffer in either byte order.
+    struct Builder {
+        bytes: Vec<u8>,
+        big_endian: bool,
+    }
+
+    impl Builder {
+        fn new(size: i32, big_endian: bool) -> Builder {
+            let mut builder = Builder {
+                bytes: vec![0; size as usize],
+                big_endian,
+            };
+            builder.put(0, &size.to_le_bytes());
+            builder
+        }
+
+        fn put(&mut self, offset: usize, le_bytes: &[u8]) {
+            let mut raw = le_bytes.to_vec();
+            if self.big_endian {
+                raw.reverse();
+            }
+            self.bytes[offset..offset + raw.len()].copy_from_slice(&raw);
+        }
+
+        fn raw(&mut self, offset: usize, raw: &[u8]) {
+            self.bytes[offset..offset + raw.len()].copy_from_slice(raw);
+        }
+    }
+
+    /// A 64x64x32 image with 200 volumes, 3 mm voxels and a TR of 2000 ms.
+    fn nifti1(big_endian: bool) -> Vec<u8> {
+        let mut header = Builder::new(NIFTI1_HEADER_SIZE, big_endian);
+        for (i, dim) in [4i16, 64, 64, 32, 200, 1, 1, 1].iter().enumerate() {
+            header.put(40 + 2 * i, &dim.to_le_bytes());
+        }
+        header.put(70, &16i16.to_le_bytes());
+        for (i, pixdim) in [1.0f32, 3.0, 3.0, 3.0, 2000.0].iter().enumerate() {
+            header.put(76 + 4 * i, &pixdim.to_le_bytes());
+        }
+        // Millimetres and milliseconds.
+        header.raw(123, &[2 | 16]);
+        header.put(254, &1i16.to_le_bytes());
+        let srow = [
+            [3.0f32, 0.0, 0.0, -90.0],
+            [0.0, 3.0, 0.0, -126.0],
+            [0.0, 0.0, 3.0, -72.0],
+        ];
+        for (i, value) in srow.iter().flatten().enumerate() {
+            header.put(280 + 4 * i, &value.to_le_bytes());
+        }
+        header.raw(344, b"n+1\0");
+        header.bytes
+    }
+
+    /// A 3D 91x109x91 float64 image with 2 mm voxels and only a qform.
+    fn nifti2() -> Vec<u8> {
+        let mut header = Builder::new(NIFTI2_HEADER_SIZE, false);
+        header.raw(4, b"n+2\0\r\n\x1a\n");
+        header.put(12, &64i16.to_le_bytes());
+        for (i, dim) in [3i64, 91, 109, 91, 1, 1, 1, 1].iter().enumerate() {
+            header.put(16 + 8 * i, &dim.to_le_bytes());
+        }
+        for (i, pixdim) in [1.0f64, 2.0, 2.0, 2.0].iter().enumerate() {
+            header.put(104 + 8 * i, &pixdim.to_le_bytes());
+        }
+        header.put(344, &1i32.to_le_bytes());
+        for (i, offset) in [-90.0f64, -126.0, -72.0].iter().enumerate() {
+            header.put(352 + 8 * (3 + i), &offset.to_le_bytes());
+        }
+        header.bytes
+    }
+
+    fn write(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("nifti-test-{}-{}", std::process::id(), name));
+        if name.ends_with(".gz") {
+            let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::fast());
+            encoder.write_all(bytes).unwrap();
+            encoder.finish().unwrap();
+        } else {
+            std::fs::write(&path, bytes).unwrap();
+        }
+        path
+    }
+
+    fn read(name: &str, bytes: &[u8]) -> Result<NiftiHeader, String> {
+        let path = write(name, bytes);
+        let header = read_header(&path);
+        let _ = std::fs::remove_file(path);
+        header
+    }
+
+    fn assert_nifti1(header: &NiftiHeader) {
+        assert_eq!(header.version, 1);
+        assert_eq!(header.dimensions, vec![64, 64, 32, 200]);
+        assert_eq!(header.voxel_sizes, vec![3.0, 3.0, 3.0]);
+        assert_eq!(header.volumes, 200);
+        assert_eq!(header.repetition_time, Some(2.0));
+        assert_eq!(header.datatype, "float32");
+        assert_eq!(header.sform_code, 1);
+        assert_eq!(header.affine[0], [3.0, 0.0, 0.0, -90.0]);
+        assert_eq!(header.affine[2], [0.0, 0.0, 3.0, -72.0]);
+    }
+
+    #[test]
+    fn reads_nifti1() {
+        assert_nifti1(&read("le.nii", &nifti1(false)).unwrap());
+    }
+
+    #[test]
+    fn reads_byte_swapped_nifti1() {
+        assert_nifti1(&read("be.nii", &nifti1(true)).unwrap());
+    }
+
+    #[test]
+    fn reads_gzipped_nifti1_with_image_data() {
+        let mut bytes = nifti1(false);
+        bytes.resize(bytes.len() + 4 + 4096, 0);
+        assert_nifti1(&read("gz.nii.gz", &bytes).unwrap());
+    }
+
+    #[test]
+    fn reads_nifti2() {
+        let header = read("v2.nii", &nifti2()).unwrap();
+        assert_eq!(header.version, 2);
+        assert_eq!(header.dimensions, vec![91, 109, 91]);
+        assert_eq!(header.voxel_sizes, vec![2.0, 2.0, 2.0]);
+        assert_eq!(header.volumes, 1);
+        assert_eq!(header.repetition_time, None);
+        assert_eq!(header.datatype, "float64");
+        // An identity quaternion leaves only the voxel sizes and offsets.
+        assert_eq!(header.affine[0], [2.0, 0.0, 0.0, -90.0]);
+        assert_eq!(header.affine[1], [0.0, 2.0, 0.0, -126.0]);
+        assert_eq!(header.affine[2], [0.0, 0.0, 2.0, -72.0]);
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = nifti1(false);
+        bytes[344..348].copy_from_slice(b"abcd");
+        let error = read("magic1.nii", &bytes).unwrap_err();
+        assert!(
+            error.contains("NIfTI-1 header but not its magic"),
+            "{}",
+            error
+        );
+
+        let mut bytes = nifti2();
+        bytes[4..8].copy_from_slice(b"n+1\0");
+        let error = read("magic2.nii", &bytes).unwrap_err();
+        assert!(
+            error.contains("NIfTI-2 header but not its magic"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let error = read("other.nii", &[0; 400]).unwrap_err();
+        assert!(error.contains("does not have a NIfTI-1 or NIfTI-2 header"));
+        let error = read("short.nii", &nifti1(false)[..200]).unwrap_err();
+        assert!(error.contains("ends inside its NIfTI header"));
+    }
+}
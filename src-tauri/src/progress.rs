@@ -0,0 +1,308 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use std::time::Instant;
+
+/// The steps of a tedana workflow, in the order tedana runs them.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum TedanaStage {
+    Starting,
+    LoadingData,
+    AdaptiveMask,
+    T2sFit,
+    Pca,
+    Ica,
+    ComponentSelection,
+    Report,
+    Done,
+}
+
+impl TedanaStage {
+    /// Rough share of a typical run that is complete when the stage starts.
+    fn start_percent(self) -> f64 {
+        match self {
+            TedanaStage::Starting => 0.0,
+            TedanaStage::LoadingData => 2.0,
+            TedanaStage::AdaptiveMask => 8.0,
+            TedanaStage::T2sFit => 12.0,
+            TedanaStage::Pca => 25.0,
+            TedanaStage::Ica => 40.0,
+            TedanaStage::ComponentSelection => 75.0,
+            TedanaStage::Report => 85.0,
+            TedanaStage::Done => 100.0,
+        }
+    }
+}
+
+/// Emitted as `tedana-progress` whenever a log line moves a job forward.
+#[derive(Debug, Serialize, Clone)]
+pub struct TedanaProgress {
+    pub job_id: u64,
+    pub stage: TedanaStage,
+    pub percent: f64,
+    /// Number of ICA attempts so far, including restarts after a failure to
+    /// converge.
+    pub ica_attempts: u32,
+    /// Iterations reported by the most recent ICA attempt.
+    pub ica_iterations: Option<u32>,
+    pub elapsed_secs: f64,
+    pub eta_secs: Option<f64>,
+}
+
+// Checked in order, so later stages win when a line mentions several.
+static STAGE_PATTERNS: Lazy<Vec<(TedanaStage, Regex)>> = Lazy::new(|| {
+    [
+        (TedanaStage::Done, r"(?i)workflow completed"),
+        (
+            TedanaStage::Report,
+            r"(?i)generating (dynamic )?report|static component maps|making figures",
+        ),
+        (
+            TedanaStage::ComponentSelection,
+            r"(?i)selecting components|decision tree|component selection",
+        ),
+        (TedanaStage::Ica, r"\bICA\b"),
+        (TedanaStage::Pca, r"\bPCA\b|(?i)selected \d+ components"),
+        (
+            TedanaStage::T2sFit,
+            r"(?i)T2\*|\bS0\b|optimal combination|fitting (a )?monoexponential",
+        ),
+        (
+            TedanaStage::AdaptiveMask,
+            r"(?i)adaptive mask|computing (epi )?mask",
+        ),
+        (
+            TedanaStage::LoadingData,
+            r"(?i)loading input data|using output directory",
+        ),
+    ]
+    .into_iter()
+    .map(|(stage, pattern)| (stage, Regex::new(pattern).unwrap()))
+    .collect()
+});
+
+static ICA_CONVERGED: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)converged in (\d+) iterations").unwrap());
+static ICA_FAILED: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)failed to converge after (\d+) iterations").unwrap());
+
+/// Follows one tedana run's log and turns the lines it recognises into
+/// progress updates.
+pub struct ProgressTracker {
+    job_id: u64,
+    started: Instant,
+    stage: TedanaStage,
+    ica_attempts: u32,
+    ica_iterations: Option<u32>,
+}
+
+impl ProgressTracker {
+    pub fn new(job_id: u64) -> Self {
+        ProgressTracker {
+            job_id,
+            started: Instant::now(),
+            stage: TedanaStage::Starting,
+            ica_attempts: 0,
+            ica_iterations: None,
+        }
+    }
+
+    /// Returns an update when the line tells us something new, and `None`
+    /// for lines that aren't recognised.
+    pub fn observe(&mut self, line: &str) -> Option<TedanaProgress> {
+        let stage = STAGE_PATTERNS
+            .iter()
+            .find(|(_, pattern)| pattern.is_match(line))
+            .map(|(stage, _)| *stage)?;
+
+        let mut changed = false;
+        if stage == TedanaStage::Ica {
+            if let Some(captures) = ICA_CONVERGED
+                .captures(line)
+                .or_else(|| ICA_FAILED.captures(line))
+            {
+                self.ica_attempts += 1;
+                self.ica_iterations = captures[1].parse().ok();
+                changed = true;
+            }
+        }
+        // Stages never go backwards; a late mention of, say, the mask during
+        // reporting doesn't reset the progress bar.
+        if stage > self.stage {
+            self.stage = stage;
+            changed = true;
+        }
+
+        changed.then(|| self.snapshot())
+    }
+
+    fn snapshot(&self) -> TedanaProgress {
+        let mut percent = self.stage.start_percent();
+        if self.stage == TedanaStage::Ica {
+            // Each attempt gets a little further into the ICA share of the run
+            // without ever claiming the stage is finished.
+            let ica_share = TedanaStage::ComponentSelection.start_percent() - percent;
+            percent += ica_share * (1.0 - 1.0 / (self.ica_attempts as f64 + 2.0));
+        }
+
+        let elapsed_secs = self.started.elapsed().as_secs_f64();
+        let eta_secs =
+            (percent > 0.0 && percent < 100.0).then(|| elapsed_secs * (100.0 - percent) / percent);
+
+        TedanaProgress {
+            job_id: self.job_id,
+            stage: self.stage,
+            percent,
+            ica_attempts: self.ica_attempts,
+            ica_iterations: self.ica_iterations,
+            elapsed_secs,
+            eta_secs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observe(tracker: &mut ProgressTracker, line: &str) -> Option<(TedanaStage, f64)> {
+        tracker
+            .observe(line)
+            .map(|progress| (progress.stage, progress.percent))
+    }
+
+    #[test]
+    fn follows_the_stages_of_a_run() {
+        let mut tracker = ProgressTracker::new(1);
+        let lines = [
+            (
+                "INFO     tedana:tedana_workflow:488 Using output directory: /data/out",
+                TedanaStage::LoadingData,
+                2.0,
+            ),
+            (
+                "INFO     tedana:tedana_workflow:580 Computing EPI mask from first echo using nilearn's compute_epi_mask function",
+                TedanaStage::AdaptiveMask,
+                8.0,
+            ),
+            (
+                "INFO     tedana:tedana_workflow:640 Computing T2* map",
+                TedanaStage::T2sFit,
+                12.0,
+            ),
+            (
+                "INFO     pca:tedpca:219 Computing PCA of optimally combined multi-echo data with selection criteria: aic",
+                TedanaStage::Pca,
+                25.0,
+            ),
+            (
+                "INFO     tedica:automatic_selection:54 Performing ICA component selection with tree: tedana_orig",
+                TedanaStage::ComponentSelection,
+                75.0,
+            ),
+            (
+                "INFO     tedana:tedana_workflow:890 Making figures folder with static component maps and timecourses.",
+                TedanaStage::Report,
+                85.0,
+            ),
+            (
+                "INFO     tedana:tedana_workflow:960 Workflow completed",
+                TedanaStage::Done,
+                100.0,
+            ),
+        ];
+        for (line, stage, percent) in lines {
+            assert_eq!(
+                observe(&mut tracker, line),
+                Some((stage, percent)),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn counts_ica_attempts() {
+        let mut tracker = ProgressTracker::new(1);
+        let failed = tracker
+            .observe("WARNING  ica:tedica:85 ICA with random seed 42 failed to converge after 500 iterations")
+            .unwrap();
+        assert_eq!(failed.stage, TedanaStage::Ica);
+        assert_eq!(failed.ica_attempts, 1);
+        assert_eq!(failed.ica_iterations, Some(500));
+        assert!((failed.percent - (40.0 + 35.0 * 2.0 / 3.0)).abs() < 1e-9);
+
+        let converged = tracker
+            .observe("INFO     ica:tedica:93 ICA with random seed 43 converged in 63 iterations")
+            .unwrap();
+        assert_eq!(converged.stage, TedanaStage::Ica);
+        assert_eq!(converged.ica_attempts, 2);
+        assert_eq!(converged.ica_iterations, Some(63));
+        assert!((converged.percent - (40.0 + 35.0 * 3.0 / 4.0)).abs() < 1e-9);
+        assert!(converged.percent < TedanaStage::ComponentSelection.start_percent());
+    }
+
+    #[test]
+    fn ica_lines_without_a_result_only_move_the_stage_once() {
+        let mut tracker = ProgressTracker::new(1);
+        let line = "INFO     ica:tedica:70 Running FastICA with random seed 42";
+        assert_eq!(
+            observe(&mut tracker, "INFO     ica:tedica:60 Initializing ICA"),
+            Some((TedanaStage::Ica, 40.0 + 35.0 / 2.0))
+        );
+        assert_eq!(observe(&mut tracker, line), None);
+    }
+
+    #[test]
+    fn never_goes_backwards_or_reports_unknown_lines() {
+        let mut tracker = ProgressTracker::new(1);
+        assert_eq!(
+            observe(
+                &mut tracker,
+                "INFO     tedana:tedana_workflow:920 Generating dynamic report"
+            ),
+            Some((TedanaStage::Report, 85.0))
+        );
+        assert_eq!(
+            observe(
+                &mut tracker,
+                "INFO     utils:make_adaptive_mask:150 Computing adaptive mask"
+            ),
+            None
+        );
+        assert_eq!(
+            observe(
+                &mut tracker,
+                "INFO     io:write:45 Writing desc-optcom_bold.nii.gz"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn other_fitting_lines_are_not_the_t2s_fit() {
+        let mut tracker = ProgressTracker::new(1);
+        assert_eq!(
+            observe(
+                &mut tracker,
+                "INFO     tedana:tedana_workflow:580 Computing EPI mask from first echo"
+            ),
+            Some((TedanaStage::AdaptiveMask, 8.0))
+        );
+        assert_eq!(
+            observe(
+                &mut tracker,
+                "INFO     gsr:gscontrol_raw:90 Fitting the global signal out of the data"
+            ),
+            None
+        );
+        assert_eq!(
+            observe(
+                &mut tracker,
+                "INFO     decay:fit_decay:400 Fitting a monoexponential model to the data"
+            ),
+            Some((TedanaStage::T2sFit, 12.0))
+        );
+    }
+}
@@ -0,0 +1,360 @@
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvironmentKind {
+    Conda,
+    Venv,
+    Uv,
+    Pyenv,
+    Homebrew,
+    System,
+}
+
+/// A Python interpreter together with the environment it belongs to. Tools
+/// are launched with the environment's variables set directly, so no activate
+/// script is ever sourced.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PythonEnvironment {
+    pub kind: EnvironmentKind,
+    pub interpreter: PathBuf,
+    pub prefix: PathBuf,
+    pub bin_dir: PathBuf,
+}
+
+impl PythonEnvironment {
+    /// Works out the environment type from the interpreter path. The path is
+    /// not canonicalised, since venv interpreters are usually symlinks into
+    /// the base installation.
+    pub fn detect(python_path: &str, prefix_hint: Option<&str>) -> Result<Self, String> {
+        let interpreter = PathBuf::from(python_path);
+        if !interpreter.is_file() {
+            return Err(format!("Python interpreter not found: {}", python_path));
+        }
+        let bin_dir = interpreter
+            .parent()
+            .ok_or_else(|| format!("Invalid Python interpreter path: {}", python_path))?
+            .to_path_buf();
+
+        let prefix = match prefix_hint.map(PathBuf::from).filter(|p| p.is_dir()) {
+            Some(prefix) => prefix,
+            // Unix layouts keep the interpreter in <prefix>/bin, venvs on
+            // Windows use <prefix>\Scripts and conda on Windows puts
+            // python.exe directly in the prefix.
+            None if bin_dir
+                .file_name()
+                .is_some_and(|n| n == "bin" || n == "Scripts") =>
+            {
+                bin_dir.parent().unwrap_or(&bin_dir).to_path_buf()
+            }
+            None => bin_dir.clone(),
+        };
+
+        Ok(PythonEnvironment {
+            kind: detect_kind(&interpreter, &prefix),
+            interpreter,
+            prefix,
+            bin_dir,
+        })
+    }
+
+    /// A command for `program` with the environment's variables applied.
+    pub fn command<S: AsRef<std::ffi::OsStr>>(&self, program: S) -> Command {
+        let mut command = Command::new(program);
+        command.env("PATH", self.search_path());
+        command.env_remove("PYTHONHOME");
+        match self.kind {
+            EnvironmentKind::Conda => {
+                command.env("CONDA_PREFIX", &self.prefix);
+                if let Some(name) = self.prefix.file_name() {
+                    command.env("CONDA_DEFAULT_ENV", name);
+                }
+            }
+            EnvironmentKind::Venv | EnvironmentKind::Uv => {
+                command.env("VIRTUAL_ENV", &self.prefix);
+            }
+            EnvironmentKind::Pyenv | EnvironmentKind::Homebrew | EnvironmentKind::System => {}
+        }
+        command
+    }
+
+    pub fn python_command(&self) -> Command {
+        self.command(&self.interpreter)
+    }
+
+    /// Launches tedana through the environment's own entry point when it has
+    /// one, and through the interpreter otherwise.
+    pub fn tedana_command(&self) -> Command {
+        let entry_point = self.bin_dir.join(if cfg!(windows) {
+            "tedana.exe"
+        } else {
+            "tedana"
+        });
+        if entry_point.is_file() {
+            self.command(entry_point)
+        } else {
+            let mut command = self.python_command();
+            command.arg("-m").arg("tedana.workflows.tedana");
+            command
+        }
+    }
+
+    fn search_path(&self) -> OsString {
+        let mut paths = vec![self.bin_dir.clone()];
+        if self.kind == EnvironmentKind::Conda && cfg!(windows) {
+            // Conda on Windows keeps DLLs and console scripts outside the prefix root.
+            paths.push(self.prefix.join("Library").join("bin"));
+            paths.push(self.prefix.join("Scripts"));
+        }
+        if let Some(existing) = std::env::var_os("PATH") {
+            paths.extend(std::env::split_paths(&existing));
+        }
+        std::env::join_paths(paths).unwrap_or_default()
+    }
+}
+
+fn detect_kind(interpreter: &Path, prefix: &Path) -> EnvironmentKind {
+    if prefix.join("conda-meta").is_dir() {
+        return EnvironmentKind::Conda;
+    }
+
+    if let Ok(config) = fs::read_to_string(prefix.join("pyvenv.cfg")) {
+        let created_by_uv = config
+            .lines()
+            .any(|line| line.split('=').next().is_some_and(|key| key.trim() == "uv"));
+        return if created_by_uv {
+            EnvironmentKind::Uv
+        } else {
+            EnvironmentKind::Venv
+        };
+    }
+
+    let pyenv_root = std::env::var_os("PYENV_ROOT")
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".pyenv")));
+    if pyenv_root.is_some_and(|root| interpreter.starts_with(root.join("versions"))) {
+        return EnvironmentKind::Pyenv;
+    }
+
+    let homebrew_prefixes = [
+        "/opt/homebrew",
+        "/usr/local/Cellar",
+        "/usr/local/opt",
+        "/home/linuxbrew/.linuxbrew",
+    ];
+    if homebrew_prefixes
+        .iter()
+        .any(|brew| interpreter.starts_with(brew))
+        || fs::canonicalize(interpreter)
+            .is_ok_and(|real| homebrew_prefixes.iter().any(|brew| real.starts_with(brew)))
+    {
+        return EnvironmentKind::Homebrew;
+    }
+
+    EnvironmentKind::System
+}
+
+pub fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+/// Interpreters started at once while probing, so machines with dozens of
+/// environments don't launch dozens of Pythons together.
+const MAX_CONCURRENT_PROBES: usize = 4;
+
+/// An interpreter found on this machine that can import tedana.
+#[derive(Debug, Serialize, Clone)]
+pub struct DiscoveredEnvironment {
+    #[serde(flatten)]
+    pub environment: PythonEnvironment,
+    pub python_version: String,
+    pub tedana_version: String,
+}
+
+/// Scans the usual environment locations for interpreters and probes each of
+/// them concurrently, returning the ones that have tedana installed.
+pub async fn discover_environments(project_dirs: Vec<PathBuf>) -> Vec<DiscoveredEnvironment> {
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_PROBES));
+    let mut probes = JoinSet::new();
+    for interpreter in candidate_interpreters(&project_dirs) {
+        let Ok(environment) = PythonEnvironment::detect(&interpreter.to_string_lossy(), None)
+        else {
+            continue;
+        };
+        let permits = permits.clone();
+        probes.spawn(async move {
+            let _permit = permits.acquire_owned().await.ok()?;
+            probe_environment(environment).await
+        });
+    }
+
+    let mut discovered = Vec::new();
+    while let Some(result) = probes.join_next().await {
+        if let Ok(Some(environment)) = result {
+            discovered.push(environment);
+        }
+    }
+    discovered.sort_by(|a, b| a.environment.interpreter.cmp(&b.environment.interpreter));
+    discovered
+}
+
+async fn probe_environment(environment: PythonEnvironment) -> Option<DiscoveredEnvironment> {
+    let mut command = tokio::process::Command::from(environment.python_command());
+    command
+        .arg("-c")
+        .arg(format!(
+            "import sys; print('%d.%d.%d' % sys.version_info[:3]); {}",
+            crate::tedana::TEDANA_VERSION_PROBE
+        ))
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+
+    let output = tokio::time::timeout(PROBE_TIMEOUT, command.output())
+        .await
+        .ok()?
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines().map(str::trim);
+    let python_version = lines.next()?.to_string();
+    let tedana_version = lines.next()?.to_string();
+    Some(DiscoveredEnvironment {
+        environment,
+        python_version,
+        tedana_version,
+    })
+}
+
+/// Interpreter paths from conda, virtualenvwrapper, pyenv, uv, project-local
+/// `.venv` folders and `PATH`, with one interpreter kept per bin directory.
+fn candidate_interpreters(project_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let home = home_dir();
+    let mut prefixes: Vec<PathBuf> = Vec::new();
+
+    // Conda and mamba installations, their envs/ folders and any environments
+    // registered in ~/.conda/environments.txt.
+    let mut conda_roots: Vec<PathBuf> = Vec::new();
+    if let Some(home) = &home {
+        for name in [
+            "miniconda3",
+            "anaconda3",
+            "miniforge3",
+            "mambaforge",
+            "micromamba",
+            ".conda",
+        ] {
+            conda_roots.push(home.join(name));
+        }
+        if let Ok(registered) = fs::read_to_string(home.join(".conda").join("environments.txt")) {
+            prefixes.extend(
+                registered
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(PathBuf::from),
+            );
+        }
+    }
+    conda_roots.push(PathBuf::from("/opt/conda"));
+    conda_roots.push(PathBuf::from("/opt/miniconda3"));
+    for variable in ["CONDA_PREFIX", "MAMBA_ROOT_PREFIX"] {
+        if let Some(prefix) = std::env::var_os(variable) {
+            conda_roots.push(PathBuf::from(prefix));
+        }
+    }
+    for root in conda_roots {
+        prefixes.extend(subdirectories(&root.join("envs")));
+        prefixes.push(root);
+    }
+
+    // virtualenvwrapper, pyenv and uv-managed interpreters and tools.
+    let workon_home = std::env::var_os("WORKON_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".virtualenvs")));
+    let pyenv_root = std::env::var_os("PYENV_ROOT")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".pyenv")));
+    let uv_data = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".local").join("share")))
+        .map(|data| data.join("uv"));
+    let env_folders = [
+        workon_home,
+        pyenv_root.map(|root| root.join("versions")),
+        std::env::var_os("UV_PYTHON_INSTALL_DIR")
+            .map(PathBuf::from)
+            .or_else(|| uv_data.as_ref().map(|uv| uv.join("python"))),
+        std::env::var_os("UV_TOOL_DIR")
+            .map(PathBuf::from)
+            .or_else(|| uv_data.as_ref().map(|uv| uv.join("tools"))),
+    ];
+    for folder in env_folders.into_iter().flatten() {
+        prefixes.extend(subdirectories(&folder));
+    }
+
+    // Project-local environments next to the dataset or the app.
+    let mut project_dirs = project_dirs.to_vec();
+    if let Ok(current_dir) = std::env::current_dir() {
+        project_dirs.push(current_dir);
+    }
+    for dir in project_dirs {
+        for ancestor in dir.ancestors() {
+            prefixes.push(ancestor.join(".venv"));
+            prefixes.push(ancestor.join("venv"));
+        }
+    }
+
+    let mut bin_dirs: Vec<PathBuf> = Vec::new();
+    for prefix in prefixes {
+        bin_dirs.push(prefix.join("bin"));
+        bin_dirs.push(prefix.join("Scripts"));
+        bin_dirs.push(prefix);
+    }
+    if let Some(path) = std::env::var_os("PATH") {
+        bin_dirs.extend(std::env::split_paths(&path));
+    }
+
+    let mut seen = Vec::new();
+    let mut interpreters = Vec::new();
+    for bin_dir in bin_dirs {
+        let Some(interpreter) = ["python3", "python", "python.exe"]
+            .iter()
+            .map(|name| bin_dir.join(name))
+            .find(|path| path.is_file())
+        else {
+            continue;
+        };
+        let key = fs::canonicalize(&bin_dir).unwrap_or(bin_dir);
+        if !seen.contains(&key) {
+            seen.push(key);
+            interpreters.push(interpreter);
+        }
+    }
+    interpreters
+}
+
+fn subdirectories(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir())
+                .collect()
+        })
+        .unwrap_or_default()
+}
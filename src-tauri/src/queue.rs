@@ -0,0 +1,396 @@
+use crate::args::TedanaArgs;
+use crate::consistency;
+use crate::tedana::{self, Severity, TedanaCommand};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use tauri::Window;
+use tokio::sync::{Mutex, Notify};
+
+const QUEUE_FILE_NAME: &str = "tedana_queue.json";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    fn is_finished(self) -> bool {
+        matches!(
+            self,
+            JobState::Succeeded | JobState::Failed | JobState::Cancelled
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NewJob {
+    pub subject: String,
+    pub session: String,
+    /// Key of the functional run within the session, e.g. `task-rest_run-1`.
+    #[serde(default)]
+    pub run: String,
+    pub python_path: String,
+    #[serde(default)]
+    pub args: Option<TedanaArgs>,
+    /// Deprecated free-form argument string, used only when `args` is unset.
+    #[serde(default)]
+    pub command_args: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Job {
+    pub id: u64,
+    pub subject: String,
+    pub session: String,
+    /// Key of the functional run within the session, e.g. `task-rest_run-1`.
+    #[serde(default)]
+    pub run: String,
+    pub python_path: String,
+    #[serde(default)]
+    pub args: Option<TedanaArgs>,
+    #[serde(default)]
+    pub command_args: Option<String>,
+    pub state: JobState,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct JobQueue {
+    next_id: u64,
+    /// Upper bound on concurrently running jobs. When unset, jobs are started
+    /// as long as their `--n-threads` fit into the available CPUs.
+    #[serde(default)]
+    max_concurrent_jobs: Option<usize>,
+    jobs: Vec<Job>,
+    /// Jobs whose `run_job` task hasn't finished, including cancelled jobs
+    /// whose process is still stopping.
+    #[serde(skip)]
+    active: HashSet<u64>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+static JOB_QUEUE: Lazy<Mutex<JobQueue>> = Lazy::new(|| Mutex::new(JobQueue::default()));
+static QUEUE_NOTIFY: Lazy<Notify> = Lazy::new(Notify::new);
+
+impl Job {
+    fn command(&self) -> TedanaCommand {
+        match (&self.args, &self.command_args) {
+            (Some(args), _) => TedanaCommand::Args(Box::new(args.clone())),
+            #[allow(deprecated)]
+            (None, command_args) => TedanaCommand::Shell(command_args.clone().unwrap_or_default()),
+        }
+    }
+}
+
+impl JobQueue {
+    fn load(path: Option<PathBuf>) -> JobQueue {
+        let mut queue = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str::<JobQueue>(&contents).ok())
+            .unwrap_or_default();
+
+        // A job that was running when the app closed never finished, so it is
+        // picked up again from the start.
+        for job in queue.jobs.iter_mut() {
+            if job.state == JobState::Running {
+                job.state = JobState::Queued;
+                job.message = Some("Restarted after the app was closed".to_string());
+            }
+        }
+
+        queue.path = path;
+        queue
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create queue directory {:?}: {}", parent, e))?;
+        }
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialise job queue: {}", e))?;
+        fs::write(path, contents)
+            .map_err(|e| format!("Failed to write job queue {:?}: {}", path, e))
+    }
+
+    fn job_mut(&mut self, job_id: u64) -> Result<&mut Job, String> {
+        self.jobs
+            .iter_mut()
+            .find(|job| job.id == job_id)
+            .ok_or_else(|| format!("No job with id {}", job_id))
+    }
+
+    fn allocate_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Marks the next queued job as running if there is capacity for it.
+    fn start_next(&mut self) -> Option<Job> {
+        let running: Vec<&Job> = self
+            .jobs
+            .iter()
+            .filter(|job| job.state == JobState::Running)
+            .collect();
+        let next_index = self
+            .jobs
+            .iter()
+            .position(|job| job.state == JobState::Queued)?;
+
+        let has_capacity = match self.max_concurrent_jobs {
+            Some(limit) => running.len() < limit.max(1),
+            None => {
+                let used_threads: usize = running
+                    .iter()
+                    .map(|job| job.command().requested_threads())
+                    .sum();
+                let next_threads = self.jobs[next_index].command().requested_threads();
+                // A job that wants more threads than the machine has still
+                // runs, just never alongside anything else.
+                running.is_empty() || used_threads + next_threads <= tedana::available_cpus()
+            }
+        };
+        if !has_capacity {
+            return None;
+        }
+
+        let job = &mut self.jobs[next_index];
+        job.state = JobState::Running;
+        job.message = None;
+        let job = job.clone();
+        self.active.insert(job.id);
+        Some(job)
+    }
+}
+
+fn commit(window: &Window, queue: &JobQueue) -> Result<(), String> {
+    window.emit("queue-changed", queue.jobs.clone()).unwrap();
+    queue.save()
+}
+
+/// Loads the persisted queue and then starts queued jobs whenever there is
+/// capacity for them, for the lifetime of the app.
+pub async fn run_job_queue(window: Window, data_dir: Option<PathBuf>) {
+    {
+        let mut queue = JOB_QUEUE.lock().await;
+        *queue = JobQueue::load(data_dir.map(|dir| dir.join(QUEUE_FILE_NAME)));
+        if let Err(e) = commit(&window, &queue) {
+            println!("Error saving job queue: {}", e);
+        }
+    }
+
+    loop {
+        {
+            let mut queue = JOB_QUEUE.lock().await;
+            let mut started = false;
+            while let Some(job) = queue.start_next() {
+                started = true;
+                tauri::async_runtime::spawn(run_job(window.clone(), job));
+            }
+            if started {
+                if let Err(e) = commit(&window, &queue) {
+                    println!("Error saving job queue: {}", e);
+                }
+            }
+        }
+        QUEUE_NOTIFY.notified().await;
+    }
+}
+
+async fn run_job(window: Window, job: Job) {
+    let result = match check_echoes(&job).await {
+        Ok(()) if tedana::take_cancelled(job.id).await => {
+            Err("Cancelled before tedana started".to_string())
+        }
+        Ok(()) => {
+            tedana::run_tedana(
+                window.clone(),
+                job.id,
+                job.python_path.clone(),
+                job.command(),
+            )
+            .await
+        }
+        Err(message) => Err(message),
+    };
+
+    let mut queue = JOB_QUEUE.lock().await;
+    queue.active.remove(&job.id);
+    // A cancel that arrived after tedana failed to start must not carry over
+    // to a retry of the job.
+    tedana::take_cancelled(job.id).await;
+    if let Ok(finished) = queue.job_mut(job.id) {
+        // A job cancelled while running keeps its cancelled state.
+        if finished.state == JobState::Running {
+            match result {
+                Ok(message) => {
+                    finished.state = JobState::Succeeded;
+                    finished.message = Some(message);
+                }
+                Err(message) => {
+                    finished.state = JobState::Failed;
+                    finished.message = Some(message);
+                }
+            }
+        }
+    }
+    if let Err(e) = commit(&window, &queue) {
+        println!("Error saving job queue: {}", e);
+    }
+    QUEUE_NOTIFY.notify_one();
+}
+
+/// Fails jobs whose echoes don't line up before tedana starts, since tedana
+/// would otherwise fail late or produce garbage.
+async fn check_echoes(job: &Job) -> Result<(), String> {
+    let Some(args) = job.args.clone() else {
+        return Ok(());
+    };
+    let issues = tauri::async_runtime::spawn_blocking(move || {
+        consistency::check_echo_files(&args.data_files, &args.echo_times)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let errors: Vec<String> = issues
+        .into_iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .map(|issue| issue.message)
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Echo consistency check failed: {}",
+            errors.join("; ")
+        ))
+    }
+}
+
+/// Reserves a job id for a run that bypasses the queue, so its events can't
+/// be confused with those of a queued job.
+pub async fn allocate_job_id() -> u64 {
+    let mut queue = JOB_QUEUE.lock().await;
+    let id = queue.allocate_id();
+    if let Err(e) = queue.save() {
+        println!("Error saving job queue: {}", e);
+    }
+    id
+}
+
+pub async fn enqueue_jobs(window: Window, new_jobs: Vec<NewJob>) -> Result<Vec<Job>, String> {
+    let mut queue = JOB_QUEUE.lock().await;
+    let mut added = Vec::new();
+    for new_job in new_jobs {
+        let job = Job {
+            id: queue.allocate_id(),
+            subject: new_job.subject,
+            session: new_job.session,
+            run: new_job.run,
+            python_path: new_job.python_path,
+            args: new_job.args,
+            command_args: new_job.command_args,
+            state: JobState::Queued,
+            message: None,
+        };
+        queue.jobs.push(job.clone());
+        added.push(job);
+    }
+    commit(&window, &queue)?;
+    QUEUE_NOTIFY.notify_one();
+    Ok(added)
+}
+
+pub async fn list_jobs() -> Vec<Job> {
+    JOB_QUEUE.lock().await.jobs.clone()
+}
+
+pub async fn reorder_job(window: Window, job_id: u64, new_index: usize) -> Result<(), String> {
+    let mut queue = JOB_QUEUE.lock().await;
+    let index = queue
+        .jobs
+        .iter()
+        .position(|job| job.id == job_id)
+        .ok_or_else(|| format!("No job with id {}", job_id))?;
+    let job = queue.jobs.remove(index);
+    let new_index = new_index.min(queue.jobs.len());
+    queue.jobs.insert(new_index, job);
+    commit(&window, &queue)
+}
+
+pub async fn cancel_job(window: Window, job_id: u64) -> Result<(), String> {
+    let pid = {
+        let mut queue = JOB_QUEUE.lock().await;
+        let job = queue.job_mut(job_id)?;
+        if job.state.is_finished() {
+            return Err(format!("Job {} has already finished", job_id));
+        }
+        let was_running = job.state == JobState::Running;
+        job.state = JobState::Cancelled;
+        job.message = Some("Cancelled by user".to_string());
+        commit(&window, &queue)?;
+        // A running job may still be checking its echoes or probing tedana,
+        // in which case there is no process to stop yet. Marking it while
+        // the queue is locked keeps `run_job` from clearing the mark early.
+        if was_running {
+            tedana::mark_cancelled(job_id).await
+        } else {
+            None
+        }
+    };
+
+    match pid {
+        Some(pid) => tedana::stop_process_group(pid).await,
+        None => Ok(()),
+    }
+}
+
+pub async fn retry_job(window: Window, job_id: u64) -> Result<(), String> {
+    let mut queue = JOB_QUEUE.lock().await;
+    // Until the earlier attempt has stopped, its result would overwrite the
+    // new attempt's and its process would block the new one from starting.
+    if queue.active.contains(&job_id) {
+        return Err(format!(
+            "Job {} is still stopping; try again in a moment",
+            job_id
+        ));
+    }
+    let job = queue.job_mut(job_id)?;
+    if !matches!(job.state, JobState::Failed | JobState::Cancelled) {
+        return Err("Only failed or cancelled jobs can be retried".to_string());
+    }
+    job.state = JobState::Queued;
+    job.message = None;
+    commit(&window, &queue)?;
+    QUEUE_NOTIFY.notify_one();
+    Ok(())
+}
+
+pub async fn get_max_concurrent_jobs() -> Option<usize> {
+    JOB_QUEUE.lock().await.max_concurrent_jobs
+}
+
+pub async fn set_max_concurrent_jobs(
+    window: Window,
+    max_concurrent_jobs: Option<usize>,
+) -> Result<(), String> {
+    let mut queue = JOB_QUEUE.lock().await;
+    queue.max_concurrent_jobs = max_concurrent_jobs;
+    commit(&window, &queue)?;
+    QUEUE_NOTIFY.notify_one();
+    Ok(())
+}
@@ -0,0 +1,481 @@
+use crate::bids::{BidsStructure, FunctionalRun, RunRef, Session, Subject};
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+/// Run properties that can be filtered on besides the tabular columns.
+const BUILTIN_COLUMNS: &[&str] = &[
+    "subject", "session", "task", "acq", "ce", "rec", "dir", "run", "space",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare {
+        column: String,
+        op: Op,
+        value: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+/// Returns the runs matching `filter`, e.g. `group == patient and site == B`
+/// or `age >= 30 and not (task == rest)`.
+///
+/// Columns are looked up in the run's `scans.tsv` rows first, then the
+/// session's and the subject's rows, so `scans.tsv` wins when a column
+/// appears at several levels. `subject`, `session` and the run entities
+/// (`task`, `run`, ...) are always available. Numbers compare numerically,
+/// everything else as text; a missing or `n/a` value matches nothing.
+pub fn filter_runs(structure: &BidsStructure, filter: &str) -> Result<Vec<RunRef>, String> {
+    let expr = Parser::new(tokenize(filter)?).parse()?;
+    let known = known_columns(structure);
+    check_columns(&expr, &known)?;
+
+    let mut matches = Vec::new();
+    for subject in &structure.subjects {
+        for session in &subject.sessions {
+            for run in &session.runs {
+                let context = RunContext {
+                    subject,
+                    session,
+                    run,
+                };
+                if context.eval(&expr) {
+                    matches.push(RunRef {
+                        subject: subject.name.clone(),
+                        session: session.name.clone(),
+                        run: run.key.clone(),
+                    });
+                }
+            }
+        }
+    }
+    Ok(matches)
+}
+
+fn known_columns(structure: &BidsStructure) -> BTreeSet<&str> {
+    let mut known: BTreeSet<&str> = BUILTIN_COLUMNS.iter().copied().collect();
+    for subject in &structure.subjects {
+        known.extend(subject.attributes.keys().map(String::as_str));
+        for session in &subject.sessions {
+            known.extend(session.attributes.keys().map(String::as_str));
+            for run in &session.runs {
+                known.extend(
+                    run.scans
+                        .iter()
+                        .flat_map(|row| row.keys().map(String::as_str)),
+                );
+            }
+        }
+    }
+    known
+}
+
+/// Rejects columns no subject, session or scan has, which are almost
+/// certainly typos.
+fn check_columns(expr: &Expr, known: &BTreeSet<&str>) -> Result<(), String> {
+    match expr {
+        Expr::And(left, right) | Expr::Or(left, right) => {
+            check_columns(left, known)?;
+            check_columns(right, known)
+        }
+        Expr::Not(inner) => check_columns(inner, known),
+        Expr::Compare { column, .. } => {
+            if known.contains(column.as_str()) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "Unknown column '{}'; available columns are {}",
+                    column,
+                    known.iter().copied().collect::<Vec<_>>().join(", ")
+                ))
+            }
+        }
+    }
+}
+
+struct RunContext<'a> {
+    subject: &'a Subject,
+    session: &'a Session,
+    run: &'a FunctionalRun,
+}
+
+impl RunContext<'_> {
+    fn eval(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::And(left, right) => self.eval(left) && self.eval(right),
+            Expr::Or(left, right) => self.eval(left) || self.eval(right),
+            Expr::Not(inner) => !self.eval(inner),
+            Expr::Compare { column, op, value } => match self.lookup(column) {
+                Some(actual) => compare(&actual, *op, value),
+                None => false,
+            },
+        }
+    }
+
+    fn lookup(&self, column: &str) -> Option<Value> {
+        let text = |value: &Option<String>| value.clone().map(Value::String);
+        let builtin = match column {
+            "subject" => Some(Value::String(self.subject.name.clone())),
+            "session" => Some(Value::String(self.session.name.clone())),
+            "task" => text(&self.run.task),
+            "acq" => text(&self.run.acq),
+            "ce" => text(&self.run.ce),
+            "rec" => text(&self.run.rec),
+            "dir" => text(&self.run.dir),
+            "run" => self.run.run.map(Value::from),
+            "space" => text(&self.run.space),
+            _ => None,
+        };
+        builtin
+            .or_else(|| {
+                self.run
+                    .scans
+                    .iter()
+                    .find_map(|row| row.get(column).filter(|value| !value.is_null()))
+                    .cloned()
+            })
+            .or_else(|| self.session.attributes.get(column).cloned())
+            .or_else(|| self.subject.attributes.get(column).cloned())
+            .filter(|value| !value.is_null())
+    }
+}
+
+fn compare(actual: &Value, op: Op, expected: &str) -> bool {
+    let ordering = match (actual.as_f64(), expected.parse::<f64>()) {
+        (Some(actual), Ok(expected)) => actual.partial_cmp(&expected),
+        _ => {
+            let actual = match actual {
+                Value::String(text) => text.clone(),
+                other => other.to_string(),
+            };
+            Some(actual.as_str().cmp(expected))
+        }
+    };
+    let Some(ordering) = ordering else {
+        return false;
+    };
+    match op {
+        Op::Eq => ordering.is_eq(),
+        Op::Ne => ordering.is_ne(),
+        Op::Lt => ordering.is_lt(),
+        Op::Le => ordering.is_le(),
+        Op::Gt => ordering.is_gt(),
+        Op::Ge => ordering.is_ge(),
+    }
+}
+
+fn tokenize(filter: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = filter.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' || c == ')' {
+            chars.next();
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some(next) if next == c => break,
+                    Some(next) => text.push(next),
+                    None => return Err("Unterminated quote in the filter".to_string()),
+                }
+            }
+            tokens.push(Token::Quoted(text));
+        } else if "=!<>".contains(c) {
+            chars.next();
+            let equals = chars.next_if_eq(&'=').is_some();
+            let op = match (c, equals) {
+                ('=', _) => Op::Eq,
+                ('!', true) => Op::Ne,
+                ('<', false) => Op::Lt,
+                ('<', true) => Op::Le,
+                ('>', false) => Op::Gt,
+                ('>', true) => Op::Ge,
+                _ => return Err("Expected '!=' in the filter".to_string()),
+            };
+            tokens.push(Token::Op(op));
+        } else {
+            let mut word = String::new();
+            while let Some(next) =
+                chars.next_if(|next| !next.is_whitespace() && !"()=!<>\"'".contains(*next))
+            {
+                word.push(next);
+            }
+            tokens.push(Token::Word(word));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over `or`, then `and`, then `not` and comparisons.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn new(tokens: Vec<Token>) -> Self {
+        Parser {
+            tokens,
+            position: 0,
+        }
+    }
+
+    fn parse(mut self) -> Result<Expr, String> {
+        if self.tokens.is_empty() {
+            return Err("The filter is empty".to_string());
+        }
+        let expr = self.parse_or()?;
+        match self.tokens.get(self.position) {
+            None => Ok(expr),
+            Some(token) => Err(format!("Unexpected {} in the filter", describe(token))),
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn next_is_keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(
+            self.tokens.get(self.position),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword)
+        );
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_and()?;
+        while self.next_is_keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_term()?;
+        while self.next_is_keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_term()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_term(&mut self) -> Result<Expr, String> {
+        if self.next_is_keyword("not") {
+            return Ok(Expr::Not(Box::new(self.parse_term()?)));
+        }
+        match self.next() {
+            Some(Token::Open) => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("Missing ')' in the filter".to_string()),
+                }
+            }
+            Some(Token::Word(column)) => {
+                let op = match self.next() {
+                    Some(Token::Op(op)) => op,
+                    _ => {
+                        return Err(format!(
+                            "Expected a comparison such as '==' after '{}'",
+                            column
+                        ))
+                    }
+                };
+                match self.next() {
+                    Some(Token::Word(value)) | Some(Token::Quoted(value)) => {
+                        Ok(Expr::Compare { column, op, value })
+                    }
+                    _ => Err(format!("Expected a value to compare '{}' with", column)),
+                }
+            }
+            Some(token) => Err(format!("Unexpected {} in the filter", describe(&token))),
+            None => Err("The filter ends too early".to_string()),
+        }
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Word(word) => format!("'{}'", word),
+        Token::Quoted(text) => format!("\"{}\"", text),
+        Token::Op(_) => "comparison".to_string(),
+        Token::Open => "'('".to_string(),
+        Token::Close => "')'".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn run(task: &str, run: Option<u32>, scans: Value) -> Value {
+        let key = match run {
+            Some(run) => format!("task-{}_run-{}", task, run),
+            None => format!("task-{}", task),
+        };
+        json!({
+            "key": key, "task": task, "acq": null, "ce": null, "rec": null, "dir": null,
+            "run": run, "space": null, "echo_nifti_file_paths": [], "sidecar_file_paths": [],
+            "metadata": [], "headers": [], "warnings": [], "mask": null, "scans": scans,
+        })
+    }
+
+    fn subject(name: &str, attributes: Value, session: Value, runs: Vec<Value>) -> Value {
+        json!({
+            "id": 0,
+            "name": name,
+            "attributes": attributes,
+            "sessions": [{ "sub_id": 0, "name": "ses-pre", "attributes": session, "runs": runs }],
+        })
+    }
+
+    fn structure() -> BidsStructure {
+        serde_json::from_value(json!({
+            "protocols": [],
+            "subjects": [
+                subject(
+                    "sub-01",
+                    json!({ "age": 34, "group": "patient", "site": "B" }),
+                    // Sessions can override subject columns.
+                    json!({ "site": "C" }),
+                    vec![
+                        run("rest", Some(1), json!([{ "motion": 0.2 }])),
+                        run("nback", None, json!([{ "motion": 0.5 }])),
+                    ],
+                ),
+                subject(
+                    "sub-02",
+                    json!({ "age": 9, "group": "control", "site": "A" }),
+                    json!({}),
+                    vec![run("rest", Some(1), json!([{ "motion": null }]))],
+                ),
+                subject(
+                    "sub-03",
+                    json!({ "age": null, "group": "patient", "site": "A" }),
+                    json!({}),
+                    vec![run("rest", Some(1), json!([]))],
+                ),
+            ],
+        }))
+        .unwrap()
+    }
+
+    fn runs(filter: &str) -> Vec<String> {
+        filter_runs(&structure(), filter)
+            .unwrap()
+            .into_iter()
+            .map(|run| format!("{}/{}", run.subject, run.run))
+            .collect()
+    }
+
+    fn error(filter: &str) -> String {
+        filter_runs(&structure(), filter).unwrap_err()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            runs("group == patient or group == control and age > 30"),
+            [
+                "sub-01/task-rest_run-1",
+                "sub-01/task-nback",
+                "sub-03/task-rest_run-1"
+            ]
+        );
+        assert_eq!(
+            runs("(group == patient or group == control) and age > 30"),
+            ["sub-01/task-rest_run-1", "sub-01/task-nback"]
+        );
+    }
+
+    #[test]
+    fn not_applies_to_the_next_term() {
+        assert_eq!(runs("not group == patient"), ["sub-02/task-rest_run-1"]);
+        assert_eq!(
+            runs("NOT (task == rest) Or subject == 'sub-03'"),
+            ["sub-01/task-nback", "sub-03/task-rest_run-1"]
+        );
+        assert_eq!(runs("not not group == control"), ["sub-02/task-rest_run-1"]);
+    }
+
+    #[test]
+    fn compares_numbers_numerically_and_text_as_text() {
+        // As text, "9" would sort after "30".
+        assert_eq!(runs("age < 30"), ["sub-02/task-rest_run-1"]);
+        assert_eq!(runs("motion >= 0.5"), ["sub-01/task-nback"]);
+        assert_eq!(runs("run == 1").len(), 3);
+        assert_eq!(
+            runs("site < B"),
+            ["sub-02/task-rest_run-1", "sub-03/task-rest_run-1"]
+        );
+        assert_eq!(
+            runs("group == \"patient\" and task != rest"),
+            ["sub-01/task-nback"]
+        );
+    }
+
+    #[test]
+    fn missing_values_match_nothing() {
+        assert_eq!(runs("age != 34"), ["sub-02/task-rest_run-1"]);
+        assert!(runs("motion < 1")
+            .iter()
+            .all(|run| run.starts_with("sub-01")));
+        assert_eq!(runs("run != 1"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn sessions_override_subjects() {
+        assert_eq!(runs("site == C").len(), 2);
+        assert!(runs("site == B").is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_columns() {
+        assert!(error("agee > 30").starts_with("Unknown column 'agee'"));
+        assert!(error("age > 30 or not (sex == F)").starts_with("Unknown column 'sex'"));
+    }
+
+    #[test]
+    fn rejects_malformed_filters() {
+        assert_eq!(error(""), "The filter is empty");
+        assert_eq!(error("age > 30 task"), "Unexpected 'task' in the filter");
+        assert_eq!(error("age > 30)"), "Unexpected ')' in the filter");
+        assert_eq!(error("(age > 30"), "Missing ')' in the filter");
+        assert_eq!(error("age > 30 and"), "The filter ends too early");
+        assert!(error("age 30").starts_with("Expected a comparison"));
+        assert!(error("age >").starts_with("Expected a value"));
+        assert_eq!(error("age ! 30"), "Expected '!=' in the filter");
+        assert_eq!(error("task = 'rest"), "Unterminated quote in the filter");
+    }
+}
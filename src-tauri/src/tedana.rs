@@ -1,113 +1,455 @@
+use crate::args::TedanaArgs;
+use crate::bids::{self, DerivativeQuery};
+use crate::capabilities::{self, TedanaCapabilities};
+use crate::consistency;
+use crate::progress::ProgressTracker;
+use crate::python_env::PythonEnvironment;
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::io::{BufRead, BufReader};
-use std::path::Path;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ExitStatus};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tauri::Window;
 use tokio::sync::Mutex;
 
-static IS_RUNNING: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
+/// How long a terminated run gets to shut down before it is killed outright.
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(10);
+const KILL_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+struct RunningProcess {
+    // The child is spawned as the leader of its own process group, so its pid
+    // is also the group id shared by bash, tedana and any Python workers.
+    pid: u32,
+    killed: bool,
+}
+
+static RUNNING_PROCESSES: Lazy<Mutex<HashMap<u64, RunningProcess>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+/// Jobs cancelled before their process was spawned, e.g. while their echoes
+/// were checked. Only changed while `RUNNING_PROCESSES` is locked, so a cancel
+/// can't fall between the check in `run_tedana` and the spawn.
+static CANCELLED_JOBS: Lazy<Mutex<HashSet<u64>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// What to run: either structured options that become an argv, or the older
+/// free-form argument string that is interpolated into a `bash -c` line.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum TedanaCommand {
+    Args(Box<TedanaArgs>),
+    #[deprecated(note = "shell interpolation breaks on quoted paths; use `TedanaCommand::Args`")]
+    Shell(String),
+}
+
+impl TedanaCommand {
+    fn n_threads(&self) -> Option<i64> {
+        match self {
+            TedanaCommand::Args(args) => args.n_threads.map(i64::from),
+            #[allow(deprecated)]
+            TedanaCommand::Shell(command_args) => {
+                let mut args = command_args.split_whitespace();
+                let mut value = None;
+                while let Some(arg) = args.next() {
+                    if arg == "--n-threads" {
+                        value = args.next();
+                    } else if let Some(inline) = arg.strip_prefix("--n-threads=") {
+                        value = Some(inline);
+                    }
+                }
+                value.and_then(|v| v.parse::<i64>().ok())
+            }
+        }
+    }
+
+    /// Number of threads the run asks for through `--n-threads`. Tedana
+    /// treats `-1` as "all available cores".
+    pub fn requested_threads(&self) -> usize {
+        match self.n_threads() {
+            Some(-1) => available_cpus(),
+            Some(n) if n > 0 => n as usize,
+            _ => 1,
+        }
+    }
+}
+
+/// A line of tedana output, tagged with the job that produced it.
+#[derive(Debug, Serialize, Clone)]
+pub struct TedanaLine {
+    pub job_id: u64,
+    pub line: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct TedanaExit {
+    pub job_id: u64,
+    pub success: bool,
+    pub code: Option<i32>,
+    pub signal: Option<i32>,
+    pub killed: bool,
+}
+
+impl TedanaExit {
+    fn from_status(job_id: u64, status: ExitStatus, killed: bool) -> Self {
+        #[cfg(unix)]
+        let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+        #[cfg(not(unix))]
+        let signal = None;
+
+        TedanaExit {
+            job_id,
+            success: status.success(),
+            code: status.code(),
+            signal,
+            killed,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match (self.code, self.signal) {
+            (Some(code), _) => format!("exit code {}", code),
+            (None, Some(signal)) => format!("signal {}", signal),
+            (None, None) => "unknown status".to_string(),
+        }
+    }
+}
 
 pub async fn run_tedana(
     window: tauri::Window,
+    job_id: u64,
     python_path: String,
-    command_args: String,
+    command: TedanaCommand,
 ) -> Result<String, String> {
-    let mut is_running = IS_RUNNING.lock().await;
-    if *is_running {
-        return Err("Tedana is already running".to_string());
-    }
-    *is_running = true;
+    let command = gate_command(&window, job_id, &python_path, command).await?;
+
+    let child = {
+        let mut running = RUNNING_PROCESSES.lock().await;
+        if running.contains_key(&job_id) {
+            return Err(format!("Job {} is already running", job_id));
+        }
+        if CANCELLED_JOBS.lock().await.remove(&job_id) {
+            return Err("Tedana was cancelled before it started".to_string());
+        }
+        let child = spawn_tedana(&window, job_id, &python_path, &command)?;
+        running.insert(
+            job_id,
+            RunningProcess {
+                pid: child.id(),
+                killed: false,
+            },
+        );
+        child
+    };
 
-    let result = run_tedana_internal(&window, &python_path, &command_args);
+    let status = tokio::task::spawn_blocking(move || {
+        let mut child = child;
+        child.wait()
+    })
+    .await
+    .map_err(|e| format!("Failed to wait on child: {}", e))
+    .and_then(|status| status.map_err(|e| format!("Failed to wait on child: {}", e)));
 
-    *is_running = false;
-    result
+    let killed = RUNNING_PROCESSES
+        .lock()
+        .await
+        .remove(&job_id)
+        .is_some_and(|process| process.killed);
+
+    let exit = TedanaExit::from_status(job_id, status?, killed);
+    window.emit("tedana-exit", exit.clone()).unwrap();
+
+    if exit.success {
+        Ok("Tedana execution completed successfully".to_string())
+    } else if exit.killed {
+        Err(format!(
+            "Tedana execution was terminated ({})",
+            exit.describe()
+        ))
+    } else {
+        Err(format!("Tedana execution failed ({})", exit.describe()))
+    }
 }
 
-fn run_tedana_internal(
+/// Adapts structured arguments to the installed tedana before launch, passing
+/// its warnings on as output lines. The deprecated string form is run as is.
+async fn gate_command(
     window: &Window,
+    job_id: u64,
     python_path: &str,
-    command_args: &str,
-) -> Result<String, String> {
-    let env_path = Path::new(python_path).parent().unwrap().parent().unwrap();
-    let activate_script = env_path.join("bin").join("activate");
-    let activate_command = format!(". {}", activate_script.display());
-    let tedana_command = format!("tedana {}", command_args);
+    command: TedanaCommand,
+) -> Result<TedanaCommand, String> {
+    let TedanaCommand::Args(args) = command else {
+        return Ok(command);
+    };
 
-    let mut child = Command::new("bash")
-        .arg("-c")
-        .arg(&format!("{}; {}", activate_command, tedana_command))
+    let python = python_path.to_string();
+    let capabilities = tokio::task::spawn_blocking(move || TedanaCapabilities::for_python(&python))
+        .await
+        .map_err(|e| format!("Failed to check tedana capabilities: {}", e))??;
+
+    match capabilities::gate_args(&args, &capabilities) {
+        Ok((gated, warnings)) => {
+            for warning in warnings {
+                window
+                    .emit(
+                        "tedana-output",
+                        TedanaLine {
+                            job_id,
+                            line: format!("WARNING: {}", warning.message),
+                        },
+                    )
+                    .unwrap();
+            }
+            Ok(TedanaCommand::Args(Box::new(gated)))
+        }
+        Err(errors) => Err(errors
+            .into_iter()
+            .map(|error| error.message)
+            .collect::<Vec<_>>()
+            .join("; ")),
+    }
+}
+
+fn spawn_tedana(
+    window: &Window,
+    job_id: u64,
+    python_path: &str,
+    tedana_command: &TedanaCommand,
+) -> Result<Child, String> {
+    let environment = PythonEnvironment::detect(python_path, None)?;
+    let mut command = match tedana_command {
+        TedanaCommand::Args(args) => {
+            let mut command = environment.tedana_command();
+            command.args(args.to_argv());
+            command
+        }
+        #[allow(deprecated)]
+        TedanaCommand::Shell(command_args) => {
+            // The environment's bin directory is first on PATH, so `tedana`
+            // resolves to its entry point without sourcing anything.
+            let mut command = environment.command("bash");
+            command.arg("-c").arg(format!("tedana {}", command_args));
+            command
+        }
+    };
+    command
         .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+    let mut child = command
         .spawn()
         .map_err(|e| format!("Failed to start process: {}", e))?;
 
     let stdout = child.stdout.take().unwrap();
     let stderr = child.stderr.take().unwrap();
 
+    // Tedana logs to both streams, so they share one tracker.
+    let tracker = Arc::new(std::sync::Mutex::new(ProgressTracker::new(job_id)));
+
     let window_clone = window.clone();
+    let tracker_clone = tracker.clone();
     std::thread::spawn(move || {
         let reader = BufReader::new(stdout);
-        for line in reader.lines() {
-            if let Ok(line) = line {
-                window_clone.emit("tedana-output", line).unwrap();
-            }
+        for line in reader.lines().map_while(Result::ok) {
+            emit_progress(&window_clone, &tracker_clone, &line);
+            window_clone
+                .emit("tedana-output", TedanaLine { job_id, line })
+                .unwrap();
         }
     });
 
     let window_clone = window.clone();
     std::thread::spawn(move || {
         let reader = BufReader::new(stderr);
-        for line in reader.lines() {
-            if let Ok(line) = line {
-                window_clone.emit("tedana-error", line).unwrap();
-            }
+        for line in reader.lines().map_while(Result::ok) {
+            emit_progress(&window_clone, &tracker, &line);
+            window_clone
+                .emit("tedana-error", TedanaLine { job_id, line })
+                .unwrap();
         }
     });
 
-    let status = child
-        .wait()
-        .map_err(|e| format!("Failed to wait on child: {}", e))?;
+    Ok(child)
+}
 
-    if status.success() {
-        Ok("Tedana execution completed successfully".to_string())
-    } else {
-        Err("Tedana execution failed".to_string())
+fn emit_progress(window: &Window, tracker: &std::sync::Mutex<ProgressTracker>, line: &str) {
+    let progress = tracker.lock().unwrap().observe(line);
+    if let Some(progress) = progress {
+        window.emit("tedana-progress", progress).unwrap();
     }
 }
 
+/// Stops the job's tedana process group with `stop_process_group`. The exit
+/// status itself is reported by `run_tedana` once the child is reaped.
 #[tauri::command]
-pub async fn kill_tedana() -> Result<(), String> {
-    let mut is_running = IS_RUNNING.lock().await;
-    if *is_running {
-        *is_running = false;
+pub async fn kill_tedana(job_id: u64) -> Result<(), String> {
+    let pid = {
+        let mut running = RUNNING_PROCESSES.lock().await;
+        match running.get_mut(&job_id) {
+            Some(process) => {
+                process.killed = true;
+                process.pid
+            }
+            None => return Err(format!("Job {} is not currently running", job_id)),
+        }
+    };
+    stop_process_group(pid).await
+}
+
+/// Cancels a job whether or not its process has started. Returns the pid of
+/// the process group to stop with `stop_process_group`; a job without one is
+/// flagged so `run_tedana` never spawns it.
+pub async fn mark_cancelled(job_id: u64) -> Option<u32> {
+    let mut running = RUNNING_PROCESSES.lock().await;
+    match running.get_mut(&job_id) {
+        Some(process) => {
+            process.killed = true;
+            Some(process.pid)
+        }
+        None => {
+            CANCELLED_JOBS.lock().await.insert(job_id);
+            None
+        }
+    }
+}
+
+/// Whether the job was cancelled before it started, clearing the flag.
+pub async fn take_cancelled(job_id: u64) -> bool {
+    let _running = RUNNING_PROCESSES.lock().await;
+    CANCELLED_JOBS.lock().await.remove(&job_id)
+}
+
+/// Sends SIGTERM to a tedana process group and escalates to SIGKILL if
+/// anything in the group is still alive after `KILL_GRACE_PERIOD`.
+pub async fn stop_process_group(pid: u32) -> Result<(), String> {
+    terminate_process_group(pid)?;
+
+    let deadline = Instant::now() + KILL_GRACE_PERIOD;
+    while Instant::now() < deadline {
+        tokio::time::sleep(KILL_POLL_INTERVAL).await;
+        if !process_group_alive(pid) {
+            return Ok(());
+        }
+    }
+
+    kill_process_group(pid)
+}
+
+/// Stops every running job at once, so they share one grace period instead
+/// of waiting for each in turn. Failures are collected rather than leaving
+/// the remaining jobs running.
+pub async fn kill_all_tedana() -> Result<(), String> {
+    let pids: Vec<u32> = RUNNING_PROCESSES
+        .lock()
+        .await
+        .values_mut()
+        .map(|process| {
+            process.killed = true;
+            process.pid
+        })
+        .collect();
+    if pids.is_empty() {
+        return Err("No Tedana process is currently running".to_string());
+    }
+
+    let mut stops = tokio::task::JoinSet::new();
+    for pid in pids {
+        stops.spawn(stop_process_group(pid));
+    }
+    let mut errors = Vec::new();
+    while let Some(stopped) = stops.join_next().await {
+        match stopped {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => errors.push(e),
+            Err(e) => errors.push(format!("Failed to stop Tedana: {}", e)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+pub fn available_cpus() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+#[cfg(unix)]
+fn signal_process_group(pid: u32, signal: libc::c_int) -> Result<(), String> {
+    // SAFETY: killpg has no memory-safety preconditions.
+    if unsafe { libc::killpg(pid as libc::pid_t, signal) } == 0 {
+        return Ok(());
+    }
+    let error = std::io::Error::last_os_error();
+    if error.raw_os_error() == Some(libc::ESRCH) {
+        // The group already exited between the check and the signal.
         Ok(())
     } else {
-        Err("No Tedana process is currently running".to_string())
+        Err(format!("Failed to signal Tedana process group: {}", error))
     }
 }
 
+#[cfg(unix)]
+fn terminate_process_group(pid: u32) -> Result<(), String> {
+    signal_process_group(pid, libc::SIGTERM)
+}
+
+#[cfg(unix)]
+fn kill_process_group(pid: u32) -> Result<(), String> {
+    signal_process_group(pid, libc::SIGKILL)
+}
+
+#[cfg(unix)]
+fn process_group_alive(pid: u32) -> bool {
+    // Signal 0 only checks whether any process in the group still exists.
+    unsafe { libc::killpg(pid as libc::pid_t, 0) == 0 }
+}
+
+#[cfg(windows)]
+fn terminate_process_group(pid: u32) -> Result<(), String> {
+    kill_process_group(pid)
+}
+
+#[cfg(windows)]
+fn kill_process_group(pid: u32) -> Result<(), String> {
+    let output = std::process::Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/T", "/F"])
+        .output()
+        .map_err(|e| format!("Failed to run taskkill: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Failed to kill Tedana process: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ))
+    }
+}
+
+#[cfg(windows)]
+fn process_group_alive(_pid: u32) -> bool {
+    false
+}
+
+/// Python snippet that prints the installed tedana version.
+pub const TEDANA_VERSION_PROBE: &str = "import tedana; print(tedana.__version__)";
+
 pub fn check_tedana_installation(
     python_path: String,
     environment_path: Option<String>,
 ) -> Result<String, String> {
-    let command_string = if let Some(env_path) = environment_path {
-        let activate_script = Path::new(&env_path).join("bin").join("activate");
-        format!(
-            "source {} && {} -c \"import tedana; print(tedana.__version__)\"",
-            activate_script.to_str().unwrap(),
-            python_path
-        )
-    } else {
-        format!(
-            "{} -c \"import tedana; print(tedana.__version__)\"",
-            python_path
-        )
-    };
-
-    let output = Command::new("sh")
+    let environment = PythonEnvironment::detect(&python_path, environment_path.as_deref())?;
+    let output = environment
+        .python_command()
         .arg("-c")
-        .arg(&command_string)
+        .arg(TEDANA_VERSION_PROBE)
         .output()
         .map_err(|e| e.to_string())?;
 
@@ -120,3 +462,363 @@ pub fn check_tedana_installation(
         ))
     }
 }
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem with one option of a tedana configuration. `field` uses the
+/// same camelCase names as `TedanaConfig` on the frontend.
+#[derive(Debug, Serialize, Clone)]
+pub struct ConfigIssue {
+    pub field: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+const TEDPCA_METHODS: &[&str] = &["mdl", "kic", "aic", "kundu", "kundu-stabilize"];
+const BUILT_IN_TREES: &[&str] = &["kundu", "minimal", "meica", "tedana_orig"];
+
+#[derive(Default)]
+struct ConfigIssues(Vec<ConfigIssue>);
+
+impl ConfigIssues {
+    fn error(&mut self, field: &str, message: String) {
+        self.push(field, Severity::Error, message);
+    }
+
+    fn warning(&mut self, field: &str, message: String) {
+        self.push(field, Severity::Warning, message);
+    }
+
+    fn push(&mut self, field: &str, severity: Severity, message: String) {
+        self.0.push(ConfigIssue {
+            field: field.to_string(),
+            severity,
+            message,
+        });
+    }
+
+    /// Adds issues for fields that don't have one yet, so an option that is
+    /// invalid for tedana in general isn't also reported against the
+    /// installed version.
+    fn extend_unreported(&mut self, issues: Vec<ConfigIssue>) {
+        for issue in issues {
+            if !self.0.iter().any(|known| known.field == issue.field) {
+                self.0.push(issue);
+            }
+        }
+    }
+}
+
+/// Checks a tedana configuration against the dataset discovered at
+/// `bids_path` and the filesystem, so misconfigured runs are caught before
+/// launch rather than minutes into tedana.
+pub fn validate_tedana_config(
+    args: &TedanaArgs,
+    bids_path: &str,
+    convention: &str,
+    derivatives: Option<&DerivativeQuery>,
+    python_path: Option<&str>,
+) -> Result<Vec<ConfigIssue>, String> {
+    let mut issues = validate_tedana_configs(
+        std::slice::from_ref(args),
+        bids_path,
+        convention,
+        derivatives,
+        python_path,
+    )?;
+    Ok(issues.pop().unwrap_or_default())
+}
+
+/// Like `validate_tedana_config` for a batch of runs, returning the issues of
+/// each in order. The dataset is scanned and tedana probed only once.
+pub fn validate_tedana_configs(
+    batch: &[TedanaArgs],
+    bids_path: &str,
+    convention: &str,
+    derivatives: Option<&DerivativeQuery>,
+    python_path: Option<&str>,
+) -> Result<Vec<Vec<ConfigIssue>>, String> {
+    let structure = match derivatives {
+        Some(query) => bids::extract_derivatives_structure(bids_path, query)?,
+        None => bids::extract_bids_structure(bids_path, convention)?,
+    };
+    let capabilities = python_path.map(TedanaCapabilities::for_python);
+
+    let mut results = Vec::new();
+    for args in batch {
+        let mut issues = ConfigIssues::default();
+        validate_inputs(args, &structure, &mut issues);
+        validate_paths(args, Path::new(bids_path), &mut issues);
+        validate_choices(args, &mut issues);
+
+        match &capabilities {
+            Some(Ok(capabilities)) => match capabilities::gate_args(args, capabilities) {
+                Ok((_, warnings)) => issues.extend_unreported(warnings),
+                Err(errors) => issues.extend_unreported(errors),
+            },
+            Some(Err(e)) => issues.warning(
+                "pythonPath",
+                format!("Could not check the installed tedana version: {}", e),
+            ),
+            None => {}
+        }
+        results.push(issues.0);
+    }
+    Ok(results)
+}
+
+fn validate_inputs(
+    args: &TedanaArgs,
+    structure: &crate::bids::BidsStructure,
+    issues: &mut ConfigIssues,
+) {
+    if args.data_files.is_empty() {
+        issues.error("dataFiles", "No echo data files were given".to_string());
+    }
+    for file in &args.data_files {
+        if file.contains("${SUBJECT}") || file.contains("${SESSION}") {
+            issues.error(
+                "dataFiles",
+                format!("{} still contains a subject or session placeholder", file),
+            );
+        } else if !Path::new(file).is_file() {
+            issues.error("dataFiles", format!("{} does not exist", file));
+        } else if !structure.subjects.iter().any(|subject| {
+            subject
+                .sessions
+                .iter()
+                .flat_map(|session| &session.runs)
+                .any(|run| run.echo_nifti_file_paths.contains(file))
+        }) {
+            issues.warning(
+                "dataFiles",
+                format!("{} is not one of the echoes found in the dataset", file),
+            );
+        }
+    }
+
+    if args.echo_times.len() != args.data_files.len() {
+        issues.error(
+            "echoTimes",
+            format!(
+                "{} echo times were given for {} data files",
+                args.echo_times.len(),
+                args.data_files.len()
+            ),
+        );
+    }
+    if args.echo_times.iter().any(|t| !t.is_finite() || *t <= 0.0) {
+        issues.error("echoTimes", "Echo times must be positive".to_string());
+    } else if args.echo_times.windows(2).any(|pair| pair[0] >= pair[1]) {
+        issues.warning(
+            "echoTimes",
+            "Echo times are not in increasing order; make sure they match the order of the data files"
+                .to_string(),
+        );
+    } else if !args.echo_times.is_empty() && args.echo_times.iter().all(|t| *t < 1.0) {
+        issues.warning(
+            "echoTimes",
+            "Echo times look like seconds; tedana expects milliseconds".to_string(),
+        );
+    }
+
+    // Compare against the sidecars of the run the data files belong to.
+    let run = args.data_files.first().and_then(|file| {
+        structure
+            .subjects
+            .iter()
+            .flat_map(|subject| &subject.sessions)
+            .flat_map(|session| &session.runs)
+            .find(|run| run.echo_nifti_file_paths.contains(file))
+    });
+    if let Some(run) = run {
+        for warning in &run.warnings {
+            issues.warning("echoTimes", format!("{} ({})", warning, run.key));
+        }
+        for issue in consistency::check_run(run) {
+            let message = format!("{} ({})", issue.message, run.key);
+            match issue.severity {
+                Severity::Error => issues.error("dataFiles", message),
+                Severity::Warning => issues.warning("dataFiles", message),
+            }
+        }
+    }
+    // Sidecar echo times are normalised to seconds, tedana takes milliseconds.
+    let run_echo_times: Vec<f64> = run
+        .map(|run| {
+            run.metadata
+                .iter()
+                .filter_map(|metadata| metadata.echo_time)
+                .map(|seconds| seconds * 1000.0)
+                .collect()
+        })
+        .unwrap_or_default();
+    if !run_echo_times.is_empty()
+        && run_echo_times.len() == args.echo_times.len()
+        && run_echo_times
+            .iter()
+            .zip(&args.echo_times)
+            .any(|(expected, given)| (expected - given).abs() > 0.01)
+    {
+        issues.warning(
+            "echoTimes",
+            format!(
+                "Echo times differ from the run's sidecars ({:?} ms)",
+                run_echo_times
+            ),
+        );
+    }
+}
+
+fn validate_paths(args: &TedanaArgs, bids_path: &Path, issues: &mut ConfigIssues) {
+    let existing_files = [
+        ("mask", &args.mask),
+        ("t2smap", &args.t2smap),
+        ("mix", &args.mix),
+    ];
+    for (field, value) in existing_files {
+        if let Some(path) = value.as_deref().filter(|p| !p.trim().is_empty()) {
+            if !Path::new(path).is_file() {
+                issues.error(field, format!("{} does not exist", path));
+            }
+        }
+    }
+
+    match args.out_dir.as_deref().filter(|p| !p.trim().is_empty()) {
+        None => issues.warning(
+            "outDir",
+            "No output directory set; tedana will write into the current directory".to_string(),
+        ),
+        Some(out_dir) => {
+            let out_dir = resolve_path(Path::new(out_dir));
+            let dataset = resolve_path(bids_path);
+            // Writing next to raw data is only acceptable under derivatives/.
+            if out_dir.starts_with(&dataset) && !out_dir.starts_with(dataset.join("derivatives")) {
+                issues.error(
+                    "outDir",
+                    format!(
+                        "The output directory is inside the input dataset {:?}; use a folder under derivatives/ or outside the dataset",
+                        dataset
+                    ),
+                );
+            }
+            if out_dir.is_file() {
+                issues.error(
+                    "outDir",
+                    format!("{:?} is a file, not a directory", out_dir),
+                );
+            }
+        }
+    }
+}
+
+fn validate_choices(args: &TedanaArgs, issues: &mut ConfigIssues) {
+    let choices: [(&str, &[String], &[&str]); 5] = [
+        ("convention", args.convention.as_slice(), &["orig", "bids"]),
+        ("maskType", &args.mask_type, &["dropout", "decay", "none"]),
+        ("fitType", args.fit_type.as_slice(), &["loglin", "curvefit"]),
+        ("combMode", args.comb_mode.as_slice(), &["t2s"]),
+        ("gscontrol", args.gscontrol.as_slice(), &["mir", "gsr"]),
+    ];
+    for (field, values, allowed) in choices {
+        for value in values.iter().filter(|v| !v.trim().is_empty()) {
+            if !allowed.contains(&value.as_str()) {
+                issues.error(
+                    field,
+                    format!("'{}' is not one of {}", value, allowed.join(", ")),
+                );
+            }
+        }
+    }
+
+    if let Some(tedpca) = args.tedpca.as_deref().filter(|v| !v.trim().is_empty()) {
+        if !is_valid_tedpca(tedpca) {
+            issues.error(
+                "tedpca",
+                format!(
+                    "'{}' must be one of {}, a variance fraction between 0 and 1, or a number of components",
+                    tedpca,
+                    TEDPCA_METHODS.join(", ")
+                ),
+            );
+        }
+    }
+
+    if let Some(tree) = args.tree.as_deref().filter(|v| !v.trim().is_empty()) {
+        if !BUILT_IN_TREES.contains(&tree) && !Path::new(tree).is_file() {
+            issues.error(
+                "tree",
+                format!(
+                    "'{}' is neither a built-in tree ({}) nor an existing JSON file",
+                    tree,
+                    BUILT_IN_TREES.join(", ")
+                ),
+            );
+        }
+    }
+
+    if let Some(n_threads) = args.n_threads {
+        if n_threads == 0 || n_threads < -1 {
+            issues.error(
+                "nThreads",
+                "Use a positive number of threads, or -1 for all cores".to_string(),
+            );
+        } else if n_threads > 0 && n_threads as usize > available_cpus() {
+            issues.warning(
+                "nThreads",
+                format!(
+                    "{} threads requested but only {} CPUs are available",
+                    n_threads,
+                    available_cpus()
+                ),
+            );
+        }
+    }
+
+    if args.maxit == Some(0) {
+        issues.error(
+            "maxit",
+            "The ICA iteration limit must be positive".to_string(),
+        );
+    }
+}
+
+fn is_valid_tedpca(value: &str) -> bool {
+    if TEDPCA_METHODS.contains(&value) {
+        return true;
+    }
+    if let Ok(components) = value.parse::<u32>() {
+        return components > 0;
+    }
+    value
+        .parse::<f64>()
+        .is_ok_and(|fraction| fraction > 0.0 && fraction < 1.0)
+}
+
+/// Canonicalises the longest existing prefix of `path`, so paths that don't
+/// exist yet can still be compared against the dataset root.
+fn resolve_path(path: &Path) -> PathBuf {
+    let mut existing = path;
+    let mut rest = Vec::new();
+    while !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+    let mut resolved = existing
+        .canonicalize()
+        .unwrap_or_else(|_| existing.to_path_buf());
+    for name in rest.into_iter().rev() {
+        resolved.push(name);
+    }
+    resolved
+}
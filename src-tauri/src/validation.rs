@@ -0,0 +1,417 @@
+use crate::bids::{applicable_sidecars, read_sidecar};
+use crate::bids_dataset::{self, BidsIgnore, DatasetDescription, DatasetType, DerivativeDataset};
+use crate::bids_entities::{self, BidsEntities};
+use crate::bids_error::BidsError;
+use crate::bids_index::BidsIndex;
+use crate::tedana::Severity;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What is wrong with a part of a dataset.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidationCode {
+    UnreadableDirectory,
+    NoSubjects,
+    InvalidSubjectLabel,
+    InvalidSessionLabel,
+    MissingFuncDirectory,
+    InvalidFilename,
+    NoMatchingFiles,
+    MissingSidecar,
+    InvalidSidecar,
+    MissingDatasetDescription,
+    InvalidDatasetDescription,
+    DerivativeDataset,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ValidationIssue {
+    pub code: ValidationCode,
+    pub severity: Severity,
+    pub message: String,
+    /// The file or directory the issue is about.
+    pub path: String,
+    /// e.g. `sub-01`; `None` for problems with the dataset as a whole.
+    pub subject: Option<String>,
+    /// e.g. `ses-1`; `None` outside a session directory.
+    pub session: Option<String>,
+    /// The underlying failure, for issues caused by an unreadable file or
+    /// directory.
+    pub error: Option<BidsError>,
+}
+
+/// Every problem found in a dataset. Subjects without errors can be processed
+/// even when others are broken.
+#[derive(Debug, Serialize, Clone)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+    pub valid_subjects: Vec<String>,
+    pub invalid_subjects: Vec<String>,
+    /// The dataset's `dataset_description.json`, when it could be read.
+    pub dataset: Option<DatasetDescription>,
+    /// Pipeline folders found under `derivatives/`.
+    pub derivatives: Vec<DerivativeDataset>,
+    /// The rules of the dataset's `.bidsignore`.
+    pub ignore_patterns: Vec<String>,
+    /// Directories and files that were skipped because of those rules.
+    pub ignored_paths: Vec<String>,
+}
+
+/// Where in the dataset an issue was found.
+#[derive(Clone, Default)]
+struct Scope {
+    subject: Option<String>,
+    session: Option<String>,
+}
+
+struct Validator<'a> {
+    dataset_root: &'a Path,
+    convention: &'a str,
+    ignore: BidsIgnore,
+    /// Directory listings shared by the sidecar lookups of every file. Never
+    /// saved, so validation always sees the dataset as it is.
+    listings: BidsIndex,
+    issues: Vec<ValidationIssue>,
+    ignored_paths: Vec<String>,
+}
+
+/// Walks the whole dataset and reports every problem instead of stopping at
+/// the first one. Only a dataset root that can't be read at all is an error.
+pub fn validate_bids_directory(
+    path: &str,
+    convention: &str,
+) -> Result<ValidationReport, BidsError> {
+    let dataset_root = Path::new(path);
+    if !dataset_root.is_dir() {
+        return Err(BidsError::not_a_directory(dataset_root));
+    }
+    fs::read_dir(dataset_root).map_err(|e| BidsError::io(dataset_root, e))?;
+
+    let mut validator = Validator {
+        dataset_root,
+        convention,
+        ignore: BidsIgnore::load(dataset_root),
+        listings: BidsIndex::default(),
+        issues: Vec::new(),
+        ignored_paths: Vec::new(),
+    };
+    let dataset = validator.check_description();
+    let subjects = validator.validate_dataset();
+
+    let (valid_subjects, invalid_subjects) = subjects.into_iter().partition(|subject| {
+        !validator.issues.iter().any(|issue| {
+            issue.severity == Severity::Error && issue.subject.as_ref() == Some(subject)
+        })
+    });
+    Ok(ValidationReport {
+        issues: validator.issues,
+        valid_subjects,
+        invalid_subjects,
+        dataset,
+        derivatives: bids_dataset::discover_derivatives(dataset_root, &validator.ignore),
+        ignore_patterns: validator.ignore.patterns().to_vec(),
+        ignored_paths: validator.ignored_paths,
+    })
+}
+
+impl Validator<'_> {
+    fn issue(
+        &mut self,
+        scope: &Scope,
+        code: ValidationCode,
+        severity: Severity,
+        path: &Path,
+        message: String,
+    ) {
+        self.issues.push(ValidationIssue {
+            code,
+            severity,
+            message,
+            path: path.to_string_lossy().into_owned(),
+            subject: scope.subject.clone(),
+            session: scope.session.clone(),
+            error: None,
+        });
+    }
+
+    fn error_issue(&mut self, scope: &Scope, code: ValidationCode, error: BidsError) {
+        self.issues.push(ValidationIssue {
+            code,
+            severity: Severity::Error,
+            message: error.to_string(),
+            path: error.path().to_string(),
+            subject: scope.subject.clone(),
+            session: scope.session.clone(),
+            error: Some(error),
+        });
+    }
+
+    /// Records `path` when `.bidsignore` excludes it.
+    fn skip_ignored(&mut self, path: &Path) -> bool {
+        let ignored = self.ignore.is_ignored(path);
+        if ignored {
+            self.ignored_paths.push(path.to_string_lossy().into_owned());
+        }
+        ignored
+    }
+
+    /// Lists the directories in `dir` whose names start with `prefix`,
+    /// sorted by name, leaving out ignored ones. Unreadable directories are
+    /// reported and treated as empty.
+    fn list_directories(
+        &mut self,
+        scope: &Scope,
+        dir: &Path,
+        prefix: &str,
+    ) -> Vec<(String, PathBuf)> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.error_issue(
+                    scope,
+                    ValidationCode::UnreadableDirectory,
+                    BidsError::io(dir, e),
+                );
+                return Vec::new();
+            }
+        };
+        let mut dirs: Vec<(String, PathBuf)> = entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+                (file_name.starts_with(prefix) && entry.file_type().ok()?.is_dir())
+                    .then(|| (file_name, entry.path()))
+            })
+            .collect();
+        dirs.sort();
+        dirs.retain(|(_, path)| !self.skip_ignored(path));
+        dirs
+    }
+
+    fn check_description(&mut self) -> Option<DatasetDescription> {
+        let root = self.dataset_root;
+        let scope = Scope::default();
+        let description_path = root.join("dataset_description.json");
+        let description = match bids_dataset::read_description(root) {
+            Ok(Some(description)) => description,
+            Ok(None) => {
+                self.issue(
+                    &scope,
+                    ValidationCode::MissingDatasetDescription,
+                    Severity::Warning,
+                    &description_path,
+                    "dataset_description.json is missing; BIDS requires it".to_string(),
+                );
+                return None;
+            }
+            Err(e) => {
+                self.issue(
+                    &scope,
+                    ValidationCode::InvalidDatasetDescription,
+                    Severity::Warning,
+                    &description_path,
+                    e.to_string(),
+                );
+                return None;
+            }
+        };
+
+        if description.bids_version.is_none() {
+            self.issue(
+                &scope,
+                ValidationCode::InvalidDatasetDescription,
+                Severity::Warning,
+                &description_path,
+                "dataset_description.json has no BIDSVersion".to_string(),
+            );
+        }
+        if description.dataset_type == DatasetType::Derivative {
+            let pipelines: Vec<String> = description
+                .generated_by
+                .iter()
+                .map(|pipeline| match &pipeline.version {
+                    Some(version) => format!("{} {}", pipeline.name, version),
+                    None => pipeline.name.clone(),
+                })
+                .collect();
+            self.issue(
+                &scope,
+                ValidationCode::DerivativeDataset,
+                Severity::Warning,
+                root,
+                format!(
+                    "This is a derivative dataset{}; choose its raw dataset and name the pipeline to use its outputs with the raw metadata",
+                    if pipelines.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" generated by {}", pipelines.join(", "))
+                    }
+                ),
+            );
+        }
+        Some(description)
+    }
+
+    /// Returns the names of all subject directories, valid or not.
+    fn validate_dataset(&mut self) -> Vec<String> {
+        let root = self.dataset_root;
+        let scope = Scope::default();
+        let sub_dirs = self.list_directories(&scope, root, "sub-");
+        if sub_dirs.is_empty() {
+            self.issue(
+                &scope,
+                ValidationCode::NoSubjects,
+                Severity::Error,
+                root,
+                "No sub-* directories found".to_string(),
+            );
+        }
+
+        for (name, sub_dir) in &sub_dirs {
+            let scope = Scope {
+                subject: Some(name.clone()),
+                session: None,
+            };
+            match bids_entities::parse_directory_label(name, "sub") {
+                Ok(_) => self.validate_subject(&scope, sub_dir),
+                Err(reason) => self.issue(
+                    &scope,
+                    ValidationCode::InvalidSubjectLabel,
+                    Severity::Error,
+                    sub_dir,
+                    reason,
+                ),
+            }
+        }
+
+        sub_dirs.into_iter().map(|(name, _)| name).collect()
+    }
+
+    fn validate_subject(&mut self, scope: &Scope, sub_dir: &Path) {
+        let ses_dirs = self.list_directories(scope, sub_dir, "ses-");
+        if ses_dirs.is_empty() {
+            self.validate_func_directory(scope, sub_dir);
+            return;
+        }
+
+        for (name, ses_dir) in &ses_dirs {
+            let scope = Scope {
+                session: Some(name.clone()),
+                ..scope.clone()
+            };
+            match bids_entities::parse_directory_label(name, "ses") {
+                Ok(_) => self.validate_func_directory(&scope, ses_dir),
+                Err(reason) => self.issue(
+                    &scope,
+                    ValidationCode::InvalidSessionLabel,
+                    Severity::Error,
+                    ses_dir,
+                    reason,
+                ),
+            }
+        }
+    }
+
+    fn validate_func_directory(&mut self, scope: &Scope, dir: &Path) {
+        let func_dir = dir.join("func");
+        if !func_dir.is_dir() {
+            self.issue(
+                scope,
+                ValidationCode::MissingFuncDirectory,
+                Severity::Error,
+                dir,
+                format!("No 'func' directory found in {:?}", dir),
+            );
+            return;
+        }
+        let entries = match fs::read_dir(&func_dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.error_issue(
+                    scope,
+                    ValidationCode::UnreadableDirectory,
+                    BidsError::io(&func_dir, e),
+                );
+                return;
+            }
+        };
+
+        let mut bold_files = Vec::new();
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if file_name.starts_with('.') || self.skip_ignored(&entry.path()) {
+                continue;
+            }
+            match BidsEntities::parse(&file_name) {
+                Ok(entities) => {
+                    if entities.matches_convention(self.convention)
+                        && (entities.is_json() || entities.is_nifti())
+                    {
+                        bold_files.push((entry.path(), entities));
+                    }
+                }
+                // Such files are skipped when the dataset is read, so they
+                // don't make the subject unusable.
+                Err(reason) => self.issue(
+                    scope,
+                    ValidationCode::InvalidFilename,
+                    Severity::Warning,
+                    &entry.path(),
+                    reason,
+                ),
+            }
+        }
+
+        if !bold_files.iter().any(|(_, entities)| entities.is_nifti()) {
+            self.issue(
+                scope,
+                ValidationCode::NoMatchingFiles,
+                Severity::Error,
+                &func_dir,
+                format!(
+                    "No NIfTI files matching the convention '{}' found in {:?}",
+                    self.convention, func_dir
+                ),
+            );
+            return;
+        }
+
+        // The sidecar may be next to the file or inherited from a higher
+        // level of the dataset.
+        let mut sidecars = Vec::new();
+        for (path, entities) in bold_files.iter().filter(|(_, f)| f.is_nifti()) {
+            let applicable = applicable_sidecars(
+                self.dataset_root,
+                &self.ignore,
+                path,
+                entities,
+                &mut self.listings,
+            );
+            if applicable.is_empty() {
+                self.issue(
+                    scope,
+                    ValidationCode::MissingSidecar,
+                    Severity::Error,
+                    path,
+                    format!(
+                        "No JSON sidecar applies to {}",
+                        path.file_name().unwrap_or_default().to_string_lossy()
+                    ),
+                );
+            }
+            sidecars.extend(applicable);
+        }
+
+        // Inherited sidecars are shared with other sessions; each is checked
+        // once per directory that uses it.
+        sidecars.sort();
+        sidecars.dedup();
+        for sidecar in sidecars {
+            if let Err(error) = read_sidecar(&sidecar) {
+                self.error_issue(scope, ValidationCode::InvalidSidecar, error);
+            }
+        }
+    }
+}
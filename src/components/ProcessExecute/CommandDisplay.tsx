@@ -64,10 +64,12 @@ const generateCommand = (config: TedanaConfig) => {
 
 const CommandDisplay = ({ config }: Props) => {
   const setCommandExecutable = useStore((state) => state.setCommandExecutable);
+  const setTedanaConfig = useStore((state) => state.setTedanaConfig);
 
   useEffect(() => {
     const command = generateCommand(config);
     setCommandExecutable(command);
+    setTedanaConfig(config);
   }, [config]);
 
   return generateCommand(config);
@@ -1,6 +1,8 @@
 import { useState, useEffect } from "react";
-import { Table, Input, Select, Toggle, CodeSnippet, Section } from "../ui";
-import { TedanaConfig, BidsStructure } from "../../util/types";
+import { invoke } from "@tauri-apps/api/tauri";
+import { Table, Input, Select, Toggle, CodeSnippet, Section, Alert } from "../ui";
+import { TedanaConfig, BidsStructure, RunRef } from "../../util/types";
+import { formatMilliseconds } from "../../util/format";
 import CommandDisplay from "./CommandDisplay";
 
 import EchoTimes from "./EchoTimes";
@@ -12,6 +14,7 @@ type Props = {
   directory: string | undefined;
   setSelectedSubjects: any;
   setSelectedSessions: any;
+  setSelectedRuns: (runs: RunRef[] | null) => void;
 };
 
 function Config({
@@ -19,12 +22,19 @@ function Config({
   directory,
   setSelectedSubjects,
   setSelectedSessions,
+  setSelectedRuns,
 }: Props) {
-  const metadata = bidsStructure?.metadata;
+  const firstRun = bidsStructure?.subjects[0]?.sessions[0]?.runs[0];
+  const metadata = firstRun?.metadata;
   const [selectedSubjectIds, setSelectedSubjectIds] = useState<number[]>([]);
   const [selectedSessionIds, setSelectedSessionIds] = useState<{
     [subjectId: number]: string[];
   }>({});
+  const [runFilter, setRunFilter] = useState<string>("");
+  const [filterMessage, setFilterMessage] = useState<{
+    type: "info" | "error";
+    content: string;
+  } | null>(null);
 
   const [config, setConfig] = useState<TedanaConfig>({
     dataFiles: [],
@@ -33,7 +43,7 @@ function Config({
     mask: "",
     prefix: "",
     convention: null,
-    maskType: "",
+    maskType: [],
     fitType: null,
     combMode: null,
     tedpca: null,
@@ -55,19 +65,13 @@ function Config({
   });
 
   useEffect(() => {
-    if (
-      bidsStructure &&
-      bidsStructure.subjects.length > 0 &&
-      bidsStructure.metadata.length > 0
-    ) {
-      // Get the first subject and session for initial data files
-      const firstSubject = bidsStructure.subjects[0];
-      const firstSession = firstSubject.sessions[0];
-
-      const newDataFiles = firstSession.echo_nifti_file_paths;
-      const newEchoTimes = bidsStructure.metadata
+    if (bidsStructure && firstRun && firstRun.metadata.length > 0) {
+      // Get the first subject's first run for initial data files
+      const newDataFiles = firstRun.echo_nifti_file_paths;
+      const newEchoTimes = firstRun.metadata
         .map((item) => item.echo_time || 0)
-        .filter((time) => time !== 0);
+        .filter((time) => time !== 0)
+        .map(formatMilliseconds);
 
       setConfig((prevConfig) => ({
         ...prevConfig,
@@ -138,6 +142,40 @@ function Config({
     );
   }, [selectedSubjectIds, selectedSessionIds, bidsStructure]);
 
+  const applyRunFilter = async () => {
+    if (!bidsStructure) return;
+    if (!runFilter.trim()) {
+      setSelectedRuns(null);
+      setFilterMessage(null);
+      return;
+    }
+
+    try {
+      const runs: RunRef[] = await invoke("filter_bids_runs", {
+        structure: bidsStructure,
+        filter: runFilter,
+      });
+      const sessionIds: { [subjectId: number]: string[] } = {};
+      bidsStructure.subjects.forEach((subject) => {
+        const sessions = subject.sessions
+          .map((session) => session.name)
+          .filter((name) =>
+            runs.some((run) => run.subject === subject.name && run.session === name)
+          );
+        if (sessions.length > 0) sessionIds[subject.id] = sessions;
+      });
+      setSelectedSubjectIds(Object.keys(sessionIds).map(Number));
+      setSelectedSessionIds(sessionIds);
+      setSelectedRuns(runs);
+      setFilterMessage({
+        type: "info",
+        content: `${runs.length} runs in ${Object.keys(sessionIds).length} subjects match the filter.`,
+      });
+    } catch (error) {
+      setFilterMessage({ type: "error", content: `${error}` });
+    }
+  };
+
   const handleInputChange = (
     e: React.ChangeEvent<HTMLInputElement | HTMLSelectElement>
   ) => {
@@ -150,13 +188,24 @@ function Config({
     setConfig((prev) => ({ ...prev, [name]: checked }));
   };
 
+  const handleMaskTypeChange = (e: React.ChangeEvent<HTMLInputElement>) => {
+    const { value, checked } = e.target;
+    const maskType = value as TedanaConfig["maskType"][number];
+    setConfig((prev) => ({
+      ...prev,
+      maskType: checked
+        ? [...prev.maskType.filter((type) => type !== maskType), maskType]
+        : prev.maskType.filter((type) => type !== maskType),
+    }));
+  };
+
   const transformedSubjects = bidsStructure?.subjects.map((subject) => ({
     id: subject.id,
     name: subject.name,
     sessions: subject.sessions.map((session) => session.name), // Assuming `session.name` is a string
   }));
 
-  if (!bidsStructure?.metadata) {
+  if (!metadata) {
     return;
   }
 
@@ -166,6 +215,22 @@ function Config({
 
       {bidsStructure && (
         <Section title="Subjects">
+          <div className="flex gap-2 mb-4">
+            <Input
+              type="text"
+              placeholder="Filter runs, e.g., group == patient and site == B"
+              value={runFilter}
+              onChange={(e) => setRunFilter(e.target.value)}
+            />
+            <button className="btn btn-secondary" onClick={applyRunFilter}>
+              Apply filter
+            </button>
+          </div>
+          {filterMessage && (
+            <div className="mb-4">
+              <Alert type={filterMessage.type} content={filterMessage.content} />
+            </div>
+          )}
           <SubjectSelector
             subjects={transformedSubjects || []}
             selectedSubjectIds={selectedSubjectIds}
@@ -181,9 +246,31 @@ function Config({
           <h2 className="text-xl font-bold mb-2"></h2>
           <Table columns={["Data Files"]} data={config.dataFiles} />
         </div>
+        {firstRun && firstRun.headers.length > 0 && (
+          <p className="text-sm">
+            Volumes per echo:{" "}
+            {firstRun.headers
+              .map((header) => (header ? header.volumes : "unreadable"))
+              .join(", ")}
+          </p>
+        )}
       </Section>
 
       <Section title="Metadata">
+        {bidsStructure && bidsStructure.protocols.length > 1 && (
+          <div className="alert alert-warning mb-4">
+            {bidsStructure.protocols.length} different acquisition protocols
+            were found. Each run is processed with the echo times from its own
+            sidecars; the values below are from {firstRun?.key}.
+          </div>
+        )}
+        {firstRun && firstRun.warnings.length > 0 && (
+          <div className="alert alert-warning mb-4">
+            {firstRun.warnings.map((warning, index) => (
+              <div key={index}>{warning}</div>
+            ))}
+          </div>
+        )}
         <div className="mb-4">
           <div className="flex">
             <EchoTimes metadata={metadata} />
@@ -237,14 +324,17 @@ function Config({
           />
         </div>
         <div className="mb-4">
-          <label className="block">Mask Type</label>
-          <Select
-            placeholder="Default (dropout)"
-            options={["dropout", "decay", "none"]}
-            name="maskType"
-            value={config.maskType}
-            onChange={(e) => handleInputChange(e)}
-          />
+          <label className="block">Mask Type (default: dropout)</label>
+          {(["dropout", "decay", "none"] as const).map((maskType) => (
+            <Toggle
+              key={maskType}
+              label={maskType}
+              name="maskType"
+              value={maskType}
+              checked={config.maskType.includes(maskType)}
+              onChange={handleMaskTypeChange}
+            />
+          ))}
         </div>
       </Section>
 
@@ -13,7 +13,7 @@ function EchoTimes({ metadata }: Props) {
     <div className="flex gap-4">
       {metadata.map((item: BoldMetadata, index: number) => (
         <div key={index} className="stats shadow">
-          <div key={index} className="stat">
+          <div key={index} className="stat" title={item.sources?.EchoTime}>
             <div className="stat-title">{`Echo ${item.echo_num}`}</div>
             <div className="stat-value">
               {!!item.echo_time && formatMilliseconds(item.echo_time)}
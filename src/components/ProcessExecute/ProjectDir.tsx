@@ -2,7 +2,8 @@ import { useState, useEffect } from "react";
 import DirectorySelector from "./DirectorySelector";
 import { InfoBlock, Alert, Input } from "../ui";
 import { invoke } from "@tauri-apps/api/tauri";
-import { BidsStructure } from "../../util/types";
+import { BidsStructure, ValidationReport } from "../../util/types";
+import { describeBidsError } from "../../util/bidsError";
 
 type Props = {
   onSuccessCallback: (
@@ -16,6 +17,9 @@ type Props = {
 function ProjectDir({ onSuccessCallback }: Props) {
   const [selectedPath, setSelectedPath] = useState<string>("");
   const [conventionString, setConventionString] = useState<string>("bold");
+  const [pipeline, setPipeline] = useState<string>("");
+  const [space, setSpace] = useState<string>("");
+  const [desc, setDesc] = useState<string>("");
   const [message, setMessage] = useState<{
     type: "info" | "success" | "warning" | "error";
     content: string;
@@ -27,10 +31,15 @@ function ProjectDir({ onSuccessCallback }: Props) {
 
     if (workingDirectory) setSelectedPath(workingDirectory);
     if (fileConvention) setConventionString(fileConvention);
+    setPipeline(localStorage.getItem("derivativesPipeline") || "");
+    setSpace(localStorage.getItem("derivativesSpace") || "");
+    setDesc(localStorage.getItem("derivativesDesc") || "");
   }, []);
 
-  const validateBIDS = async () => {
-    if (!selectedPath || !conventionString) {
+  // A rescan rereads every file instead of reusing the cached index, for
+  // when files were changed in a way the index can't detect.
+  const validateBIDS = async (rescan = false) => {
+    if (!selectedPath || (!conventionString && !pipeline)) {
       setMessage({
         type: "error",
         content: "Please select a directory and enter a file convention.",
@@ -38,32 +47,112 @@ function ProjectDir({ onSuccessCallback }: Props) {
       return;
     }
 
+    // Derivatives are not raw BIDS, so they skip the raw dataset validation.
+    if (pipeline) {
+      extractBidsStructure(rescan);
+      return;
+    }
+
     try {
-      const result: string = await invoke("validate_bids_directory", {
+      const report: ValidationReport = await invoke("validate_bids_directory", {
         path: selectedPath,
         convention: conventionString,
       });
-      setMessage({
-        type: "success",
-        content: result,
-      });
-      extractBidsStructure();
+      report.issues.forEach((issue) =>
+        console.warn(`${issue.severity} ${issue.code}: ${issue.message}`)
+      );
+      if (report.valid_subjects.length === 0) {
+        const firstError = report.issues.find(
+          (issue) => issue.severity === "error"
+        );
+        setMessage({
+          type: "error",
+          content: `No subject in this directory is BIDS-compatible with the given convention${
+            firstError && !firstError.error ? ` (${firstError.message})` : ""
+          }. ${
+            firstError?.error
+              ? describeBidsError(firstError.error)
+              : "Please review your naming conventions and try again."
+          }`,
+        });
+        return;
+      }
+      const notes = [
+        report.ignored_paths.length > 0
+          ? `${report.ignored_paths.length} paths were skipped by .bidsignore.`
+          : "",
+        report.derivatives.length > 0
+          ? `Derivatives found: ${report.derivatives
+              .map((derivative) => {
+                const pipeline = derivative.description?.generated_by[0];
+                if (!pipeline) return derivative.name;
+                const version = pipeline.version ? ` ${pipeline.version}` : "";
+                return `${derivative.name} (${pipeline.name}${version})`;
+              })
+              .join(", ")}.`
+          : "",
+      ].filter(Boolean);
+      const datasetWarning = report.issues.find(
+        (issue) => issue.code === "derivative_dataset"
+      );
+      if (report.invalid_subjects.length > 0 || datasetWarning) {
+        setMessage({
+          type: "warning",
+          content: [
+            datasetWarning?.message ?? "",
+            report.invalid_subjects.length > 0
+              ? `${report.valid_subjects.length} subjects can be used. ${report.invalid_subjects.join(", ")} ${
+                  report.invalid_subjects.length === 1 ? "has" : "have"
+                } problems and may be missing runs.`
+              : "",
+            ...notes,
+          ]
+            .filter(Boolean)
+            .join(" "),
+        });
+      } else {
+        setMessage({
+          type: "success",
+          content: [
+            `${report.dataset?.name ?? "This directory"} is BIDS-compatible${
+              report.dataset?.bids_version
+                ? ` (BIDS ${report.dataset.bids_version})`
+                : ""
+            }.`,
+            ...notes,
+          ].join(" "),
+        });
+      }
+      extractBidsStructure(rescan);
     } catch (error) {
       console.error(error);
       setMessage({
         type: "error",
-        content:
-          "This directory is not BIDS-compatible or doesn't match the given convention. Please review your naming conventions and try again.",
+        content: describeBidsError(error),
       });
     }
   };
 
-  const extractBidsStructure = async () => {
+  const extractBidsStructure = async (rescan: boolean) => {
+    const derivatives = pipeline
+      ? { pipeline, space: space || undefined, desc: desc || undefined }
+      : undefined;
     try {
-      const result: BidsStructure = await invoke("extract_bids_structure", {
-        path: selectedPath,
-        convention: conventionString,
-      });
+      const result: BidsStructure = rescan
+        ? await invoke("rescan_bids_structure", {
+            path: selectedPath,
+            convention: conventionString,
+            derivatives,
+          })
+        : derivatives
+        ? await invoke("extract_derivatives_structure", {
+            path: selectedPath,
+            derivatives,
+          })
+        : await invoke("extract_bids_structure", {
+            path: selectedPath,
+            convention: conventionString,
+          });
 
       if (Object.keys(result.subjects).length === 0) {
         setMessage({
@@ -78,7 +167,7 @@ function ProjectDir({ onSuccessCallback }: Props) {
       console.error("Error in extractBidsStructure:", error);
       setMessage({
         type: "error",
-        content: "An error occurred while extracting BIDS structure.",
+        content: describeBidsError(error),
       });
     }
   };
@@ -86,6 +175,9 @@ function ProjectDir({ onSuccessCallback }: Props) {
   const savePath = () => {
     localStorage.setItem("workingDirectory", selectedPath);
     localStorage.setItem("fileConvention", conventionString);
+    localStorage.setItem("derivativesPipeline", pipeline);
+    localStorage.setItem("derivativesSpace", space);
+    localStorage.setItem("derivativesDesc", desc);
   };
 
   const handleInputDirSelect = (path: string) => {
@@ -164,28 +256,76 @@ function ProjectDir({ onSuccessCallback }: Props) {
                   .
                 </p>
                 <p className="mt-4">
-                  tedana-app will look for files that include the text{" "}
-                  <i className="text-secondary">"_echo-[1...5]-"</i> plus the
-                  input you include below.
+                  tedana-app will look for files with an{" "}
+                  <i className="text-secondary">echo-&lt;index&gt;</i> entity
+                  whose name ends with the convention you enter above.
                 </p>
                 <p>
                   For example, if your file looked like this:{" "}
                   <i className="text-secondary">
-                    sub-1973002P_ses-3_task_echo-1_flirtboldStcMcf.nii.gz
+                    sub-1973002P_ses-3_task-rest_echo-1_desc-preproc_bold.nii.gz
                   </i>
-                  , you would enter "flirtboldStcMcf" in the input above.
+                  , you would enter "desc-preproc_bold" in the input above.
                 </p>
               </>
             }
           />
+          <div className="mt-4">
+            <h3 className="text-xl mb-2">Derivatives pipeline (optional)</h3>
+            <div className="flex gap-4">
+              <Input
+                type="text"
+                placeholder="e.g., fmriprep"
+                value={pipeline}
+                onChange={(e) => setPipeline(e.target.value)}
+              />
+              <Input
+                type="text"
+                placeholder="space, e.g., MNI152NLin2009cAsym"
+                value={space}
+                onChange={(e) => setSpace(e.target.value)}
+              />
+              <Input
+                type="text"
+                placeholder="desc (default: preproc)"
+                value={desc}
+                onChange={(e) => setDesc(e.target.value)}
+              />
+            </div>
+          </div>
+          <InfoBlock
+            fullWidth
+            title="Using preprocessed outputs?"
+            content={
+              <p>
+                Name the folder under{" "}
+                <i className="text-secondary">derivatives/</i>, or give its
+                full path, to use its echo-wise outputs instead of the raw
+                data. Runs are paired with the pipeline's{" "}
+                <i className="text-secondary">desc-brain_mask</i> when one
+                exists, and metadata missing from the derivatives is taken
+                from the raw dataset.
+              </p>
+            }
+          />
           <div className="flex justify-between">
-            <button
-              className="btn btn-primary mt-4"
-              onClick={validateBIDS}
-              disabled={!selectedPath || !conventionString}
-            >
-              Validate & Extract
-            </button>
+            <div className="flex gap-2">
+              <button
+                className="btn btn-primary mt-4"
+                onClick={() => validateBIDS()}
+                disabled={!selectedPath || (!conventionString && !pipeline)}
+              >
+                Validate & Extract
+              </button>
+              <button
+                className="btn btn-ghost mt-4"
+                onClick={() => validateBIDS(true)}
+                disabled={!selectedPath || (!conventionString && !pipeline)}
+                title="Reread every file instead of reusing what earlier scans found"
+              >
+                Full rescan
+              </button>
+            </div>
             <div className="w-full max-w-xl">
               {!!message && (
                 <Alert type={message.type} content={message.content} />
@@ -1,4 +1,5 @@
 import { useEffect, useRef } from "react";
+import { Job, TedanaProgress } from "../../util/types";
 
 interface OutputLine {
   content: string;
@@ -8,11 +9,13 @@ interface OutputLine {
 type Props = {
   output: OutputLine[];
   loading: boolean;
+  jobs: Job[];
+  progress: { [jobId: number]: TedanaProgress };
   onExecute: () => Promise<boolean>;
   onKill: () => Promise<void>;
 };
 
-function RunScript({ output, loading, onExecute, onKill }: Props) {
+function RunScript({ output, loading, jobs, progress, onExecute, onKill }: Props) {
   const outputRef = useRef<HTMLDivElement>(null);
 
   useEffect(() => {
@@ -41,6 +44,27 @@ function RunScript({ output, loading, onExecute, onKill }: Props) {
             </button>
           )}
         </div>
+        {jobs
+          .filter((job) => progress[job.id] && job.state === "running")
+          .map((job) => (
+            <div key={job.id} className="mb-2">
+              <div className="flex justify-between text-sm">
+                <span>
+                  {job.subject} {job.session} {job.run}: {progress[job.id].stage.replace(/_/g, " ")}
+                  {progress[job.id].ica_attempts > 0 &&
+                    ` (ICA attempt ${progress[job.id].ica_attempts})`}
+                </span>
+                {progress[job.id].eta_secs !== undefined && (
+                  <span>~{Math.ceil(progress[job.id].eta_secs! / 60)} min left</span>
+                )}
+              </div>
+              <progress
+                className="progress progress-primary w-full"
+                value={progress[job.id].percent}
+                max={100}
+              ></progress>
+            </div>
+          ))}
         <section className="mockup-code relative h-96 overflow-x-hidden">
           <div ref={outputRef} className="h-80 overflow-y-auto">
             {output.map((line, index) => (
@@ -2,12 +2,15 @@ import { useState, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/tauri";
 import { CodeSnippet, Input, InfoBlock } from "../ui";
 import { dirname } from "@tauri-apps/api/path";
+import { DiscoveredEnvironment } from "../../util/types";
 
 type Props = {};
 
 function PythonPathDir({}: Props) {
   const [pythonPath, setPythonPath] = useState<string>("");
   const [tedanaStatus, setTedanaStatus] = useState<string>("Not checked");
+  const [discovered, setDiscovered] = useState<DiscoveredEnvironment[]>();
+  const [discovering, setDiscovering] = useState(false);
 
   const runTedanaCheck = async (path: string) => {
     setTedanaStatus("Checking...");
@@ -54,6 +57,22 @@ function PythonPathDir({}: Props) {
     await runTedanaCheck(pythonPath);
   };
 
+  const discoverEnvironments = async () => {
+    setDiscovering(true);
+    try {
+      const workingDir = localStorage.getItem("workingDirectory");
+      const environments: DiscoveredEnvironment[] = await invoke(
+        "discover_python_environments",
+        { projectDirs: workingDir ? [workingDir] : [] }
+      );
+      setDiscovered(environments);
+    } catch (error) {
+      console.error("Error discovering Python environments:", error);
+      setDiscovered([]);
+    }
+    setDiscovering(false);
+  };
+
   const getEnvironmentPath = async (path: string): Promise<string> => {
     const pythonDir = await dirname(path);
     const envPath = await dirname(pythonDir);
@@ -79,7 +98,32 @@ function PythonPathDir({}: Props) {
           <button onClick={savePath} className="btn btn-primary ml-2">
             Save and Check
           </button>
+          <button
+            onClick={discoverEnvironments}
+            className="btn btn-secondary ml-2"
+            disabled={discovering}
+          >
+            {discovering ? "Searching..." : "Find Environments"}
+          </button>
           <p>Tedana Status: {tedanaStatus}</p>
+          {discovered && (
+            <ul className="menu bg-base-200 rounded-box my-2 max-w-lg">
+              {discovered.length === 0 && (
+                <li className="p-2">No environments with tedana were found.</li>
+              )}
+              {discovered.map((env) => (
+                <li key={env.interpreter}>
+                  <a onClick={() => setPythonPath(env.interpreter)}>
+                    {env.interpreter}
+                    <span className="badge badge-outline">{env.kind}</span>
+                    <span className="text-xs">
+                      Python {env.python_version}, tedana {env.tedana_version}
+                    </span>
+                  </a>
+                </li>
+              ))}
+            </ul>
+          )}
           <InfoBlock
             title="Not sure where to find your Python executable path?"
             content={
@@ -1,4 +1,5 @@
 import { create } from 'zustand'
+import { TedanaConfig } from '../util/types'
 
 interface StoreState {
   inputDir: string
@@ -6,12 +7,14 @@ interface StoreState {
   pythonPath: string
   tedanaStatus: string
   commandExecutable: string
+  tedanaConfig: TedanaConfig | null
 
   setInputDir: (dir: string) => void
   setOutputDir: (dir: string) => void
   setPythonPath: (path: string) => void
   setTedanaStatus: (status: string) => void
   setCommandExecutable: (commandExecutable: string) => void
+  setTedanaConfig: (tedanaConfig: TedanaConfig) => void
 }
 
 const useStore = create<StoreState>((set) => ({
@@ -21,13 +24,15 @@ const useStore = create<StoreState>((set) => ({
   pythonPath: '',
   tedanaStatus: 'Not checked',
   commandExecutable: '',
+  tedanaConfig: null,
 
   // Actions
   setInputDir: (dir) => set({ inputDir: dir }),
   setOutputDir: (dir) => set({ outputDir: dir }),
   setPythonPath: (path) => set({ pythonPath: path }),
   setTedanaStatus: (status) => set({ tedanaStatus: status }),
-  setCommandExecutable: (commandExecutable) => set({ commandExecutable: commandExecutable})
+  setCommandExecutable: (commandExecutable) => set({ commandExecutable: commandExecutable}),
+  setTedanaConfig: (tedanaConfig) => set({ tedanaConfig: tedanaConfig })
 }))
 
 export default useStore
\ No newline at end of file
@@ -0,0 +1,49 @@
+import { BidsError } from "./types";
+
+function isBidsError(error: unknown): error is BidsError {
+  return (
+    typeof error === "object" &&
+    error !== null &&
+    "category" in error &&
+    "path" in error
+  );
+}
+
+function location(error: BidsError) {
+  const parts = [error.subject, error.session].filter(Boolean);
+  return parts.length > 0 ? ` (${parts.join(", ")})` : "";
+}
+
+function detail(error: BidsError) {
+  return "message" in error ? error.message : "";
+}
+
+/**
+ * Turns an error from the BIDS commands into a message that says what to fix.
+ * Errors that aren't a `BidsError` are shown as they are.
+ */
+export function describeBidsError(error: unknown): string {
+  if (!isBidsError(error)) return `${error}`;
+  const where = `${error.path}${location(error)}`;
+  switch (error.category) {
+    case "wrong_folder":
+      return `${error.path} is not a folder. Please choose the dataset's root folder.`;
+    case "permission":
+      return `Permission denied reading ${where}. Check that your user can read this folder and its files.`;
+    case "missing":
+      return error.kind === "missing"
+        ? `${detail(error)}: ${where}.`
+        : `${where} no longer exists. Was it moved or deleted?`;
+    case "malformed": {
+      const position =
+        error.kind === "json" && error.line
+          ? ` (line ${error.line}, column ${error.column})`
+          : "";
+      return `${where} could not be read${position}: ${detail(error)}. Please fix the file and try again.`;
+    }
+    case "naming":
+      return `${where}: ${detail(error)}. Please rename it following the BIDS naming rules.`;
+    default:
+      return `Failed to read ${where}: ${detail(error)}`;
+  }
+}
@@ -0,0 +1,12 @@
+import { DerivativeQuery } from './types';
+
+// The derivatives pipeline chosen in the directory selection, if any.
+export function storedDerivativesQuery(): DerivativeQuery | null {
+  const pipeline = localStorage.getItem('derivativesPipeline');
+  if (!pipeline) return null;
+  return {
+    pipeline,
+    space: localStorage.getItem('derivativesSpace') || undefined,
+    desc: localStorage.getItem('derivativesDesc') || undefined,
+  };
+}
@@ -0,0 +1,54 @@
+import { useEffect, useRef } from 'react';
+import { invoke } from '@tauri-apps/api/tauri';
+import { listen } from '@tauri-apps/api/event';
+import { storedDerivativesQuery } from '../derivatives';
+import { BidsStructure, StructureChange } from '../types';
+
+// Watches the dataset at `path` with the stored convention and derivatives
+// pipeline, calling `onChange` whenever its subjects, sessions or runs change
+// on disk. `structure` is what the view already read from `path`, so
+// changes are diffed against it. Watching starts once both are set and stops
+// when `path` changes or the component unmounts; each view has a watch of
+// its own.
+export function useBidsWatch(
+  path: string | undefined,
+  structure: BidsStructure | undefined,
+  onChange: (change: StructureChange) => void
+) {
+  const onChangeRef = useRef(onChange);
+  onChangeRef.current = onChange;
+  const structureRef = useRef(structure);
+  structureRef.current = structure;
+  const ready = Boolean(path && structure);
+
+  useEffect(() => {
+    if (!path || !structureRef.current) return;
+
+    let watchId: number | undefined;
+    const changed = listen<StructureChange>('bids-structure-changed', (event) => {
+      if (event.payload.watch_id === watchId) onChangeRef.current(event.payload);
+    });
+    const watching = invoke<number>('watch_bids_dataset', {
+      path,
+      convention: localStorage.getItem('fileConvention') || '',
+      derivatives: storedDerivativesQuery(),
+      structure: structureRef.current,
+    })
+      .then(id => {
+        watchId = id;
+        return id;
+      })
+      .catch(error => {
+        console.error('Error watching dataset:', error);
+        return undefined;
+      });
+
+    return () => {
+      changed.then(f => f());
+      // Also stops a watch that only started after the view went away.
+      watching.then(id => {
+        if (id !== undefined) invoke('unwatch_bids_dataset', { watchId: id });
+      });
+    };
+  }, [path, ready]);
+}
@@ -1,72 +1,174 @@
-import { useState, useCallback, useEffect } from 'react';
+import { useState, useCallback, useEffect, useRef } from 'react';
 import { invoke } from '@tauri-apps/api/tauri';
 import { listen } from '@tauri-apps/api/event';
 import useStore from '../../store/useStore';
+import { formatMilliseconds } from '../format';
+import { storedDerivativesQuery } from '../derivatives';
+import { BidsStructure, ConfigIssue, FunctionalRun, Job, RunRef, TedanaConfig, TedanaLine, TedanaProgress } from '../types';
+
+const isFinished = (job: Job) =>
+  job.state === 'succeeded' || job.state === 'failed' || job.state === 'cancelled';
 
 export function useRunTedana() {
   const [output, setOutput] = useState<any[]>([]);
   const [loading, setLoading] = useState(false);
-  const { commandExecutable } = useStore();
+  const [jobs, setJobs] = useState<Job[]>([]);
+  const [progress, setProgress] = useState<{ [jobId: number]: TedanaProgress }>({});
+  const batchJobIds = useRef<number[]>([]);
+  const { commandExecutable, tedanaConfig } = useStore();
   const pythonPath = localStorage.getItem('pythonPath');
 
   useEffect(() => {
-    const output = listen('tedana-output', (event: any) => {
-      setOutput(prev => [...prev, { content: event.payload as string, isError: false }]);
+    invoke<Job[]>('list_tedana_jobs').then(setJobs);
+
+    const queueChanged = listen<Job[]>('queue-changed', (event) => {
+      setJobs(event.payload);
+      const batch = event.payload.filter(job => batchJobIds.current.includes(job.id));
+      if (batch.length > 0 && batch.every(isFinished)) {
+        batch
+          .filter(job => job.state === 'failed')
+          .forEach(job => setOutput(prev => [...prev, { content: `Execution error (${job.subject} ${job.session} ${job.run}): ${job.message}`, isError: true }]));
+        batchJobIds.current = [];
+        setLoading(false);
+      }
+    });
+
+    const progressUpdate = listen<TedanaProgress>('tedana-progress', (event) => {
+      setProgress(prev => ({ ...prev, [event.payload.job_id]: event.payload }));
     });
 
-    const error = listen('tedana-error', (event: any) => {
-      setOutput(prev => [...prev, { content: event.payload as string, isError: true }]);
+    const output = listen<TedanaLine>('tedana-output', (event) => {
+      setOutput(prev => [...prev, { content: event.payload.line, jobId: event.payload.job_id, isError: false }]);
+    });
+
+    const error = listen<TedanaLine>('tedana-error', (event) => {
+      setOutput(prev => [...prev, { content: event.payload.line, jobId: event.payload.job_id, isError: true }]);
     });
 
     return () => {
+      queueChanged.then(f => f());
+      progressUpdate.then(f => f());
       output.then(f => f());
       error.then(f => f());
     };
   }, []);
 
-  const executeTedanaCommand = useCallback(async (selectedSubjects: string[], selectedSessions: { [subjectId: string]: string[] }): Promise<boolean> => {
+  const executeTedanaCommand = useCallback(async (selectedSubjects: string[], selectedSessions: { [subjectId: string]: string[] }, bidsStructure?: BidsStructure, selectedRuns?: RunRef[] | null): Promise<boolean> => {
     setLoading(true);
     setOutput([]);
+    setProgress({});
 
     try {
-      for (const subjectId of selectedSubjects) {
-        const sessions = selectedSessions[subjectId] || [];
-        for (const sessionId of sessions) {
-          const specificCommand = generateSpecificCommand(commandExecutable, subjectId, sessionId);
-          setOutput(prev => [...prev, { content: `Executing command: ${specificCommand}`, isError: false }]);
-          console.log(`Executing for ${subjectId}, ${sessionId}: ${specificCommand}`);
-          await invoke('run_tedana_command', {
-            pythonPath,
-            commandArgs: specificCommand,
-          });
+      // One job per functional run; sessions without known runs fall back to
+      // a single job built from the configured data files. A run filter
+      // narrows the selected sessions down to the matching runs.
+      const runsFor = (subjectId: string, sessionId: string): (FunctionalRun | undefined)[] => {
+        const runs = bidsStructure?.subjects
+          .find(subject => subject.name === subjectId)
+          ?.sessions.find(session => session.name === sessionId)?.runs;
+        if (runs && selectedRuns) {
+          return runs.filter(run => selectedRuns.some(ref =>
+            ref.subject === subjectId && ref.session === sessionId && ref.run === run.key));
         }
+        return runs && runs.length > 0 ? runs : [undefined];
+      };
+      const newJobs = selectedSubjects.flatMap(subjectId =>
+        (selectedSessions[subjectId] || []).flatMap(sessionId =>
+          runsFor(subjectId, sessionId).map(run => ({
+            subject: subjectId,
+            session: sessionId,
+            run: run?.key ?? '',
+            python_path: pythonPath,
+            args: tedanaConfig ? generateSpecificConfig(tedanaConfig, subjectId, sessionId, run) : null,
+            command_args: generateSpecificCommand(commandExecutable, subjectId, sessionId, run),
+          }))
+        )
+      );
+      const bidsPath = localStorage.getItem('workingDirectory');
+      const convention = localStorage.getItem('fileConvention');
+      const derivatives = storedDerivativesQuery();
+      let hasErrors = false;
+      // One call for the whole batch, so the dataset is scanned only once.
+      const checkedJobs = newJobs.filter(job => job.args);
+      // A derivatives dataset is scanned without a naming convention.
+      if (checkedJobs.length > 0 && bidsPath && (convention || derivatives)) {
+        const batchIssues: ConfigIssue[][] = await invoke('validate_tedana_configs', {
+          args: checkedJobs.map(job => job.args),
+          bidsPath,
+          convention: convention || '',
+          derivatives,
+          pythonPath,
+        });
+        checkedJobs.forEach((job, index) => {
+          batchIssues[index].forEach(issue => {
+            hasErrors = hasErrors || issue.severity === 'error';
+            setOutput(prev => [...prev, { content: `${issue.severity.toUpperCase()} (${job.subject} ${job.session}) ${issue.field}: ${issue.message}`, isError: issue.severity === 'error' }]);
+          });
+        });
+      }
+      if (hasErrors) {
+        setOutput(prev => [...prev, { content: 'Execution error: fix the configuration errors above before running tedana', isError: true }]);
+        setLoading(false);
+        return false;
+      }
+
+      newJobs.forEach(job => {
+        setOutput(prev => [...prev, { content: `Queued command: tedana ${job.command_args}`, isError: false }]);
+      });
+      const queued: Job[] = await invoke('enqueue_tedana_jobs', { jobs: newJobs });
+      batchJobIds.current = queued.map(job => job.id);
+      if (queued.length === 0) {
+        setLoading(false);
       }
-      setLoading(false);
       return true;
     } catch (error) {
-      console.error(`Error executing tedana:`, error);
+      console.error(`Error queueing tedana jobs:`, error);
       setOutput(prev => [...prev, { content: `Execution error: ${error}`, isError: true }]);
       setLoading(false);
       return false;
     }
-  }, [commandExecutable]);
+  }, [commandExecutable, tedanaConfig]);
 
-  const generateSpecificCommand = (baseCommand: string, subjectId: string, sessionId: string) => {
+  const generateSpecificCommand = (baseCommand: string, subjectId: string, sessionId: string, run?: FunctionalRun) => {
     return baseCommand
       .replace(/\$\{SUBJECT\}/g, subjectId)
-      .replace(/\$\{SESSION\}/g, sessionId);
+      .replace(/\$\{SESSION\}/g, sessionId)
+      .replace(/\$\{RUN\}/g, run?.key ?? '');
+  };
+
+  const generateSpecificConfig = (config: TedanaConfig, subjectId: string, sessionId: string, run?: FunctionalRun): TedanaConfig => {
+    return {
+      ...config,
+      echoTimes: run && run.metadata.length > 0 && run.metadata.every(m => m.echo_time)
+        ? run.metadata.map(m => formatMilliseconds(m.echo_time!))
+        : config.echoTimes,
+      mask: run?.mask ?? config.mask,
+      dataFiles: run
+        ? run.echo_nifti_file_paths
+        : config.dataFiles.map(file =>
+          file
+            .replace(/sub-[^/]+/, subjectId)
+            .replace(/ses-[^/]+/, sessionId)
+        ),
+      outDir: run
+        ? `${config.outDir}/${subjectId}/${sessionId}/${run.key}/tedana`
+        : `${config.outDir}/${subjectId}/${sessionId}/tedana`,
+    };
   };
 
   const killTedanaExecution = useCallback(async () => {
     try {
-      await invoke('kill_tedana_command');
+      const pending = jobs.filter(job => batchJobIds.current.includes(job.id) && !isFinished(job));
+      for (const job of pending) {
+        await invoke('cancel_tedana_job', { jobId: job.id });
+      }
       setOutput((prev) => [...prev, { content: 'Tedana execution was terminated by user', isError: false }]);
       setLoading(false);
     } catch (error) {
       console.error('Error killing Tedana process:', error);
       setOutput((prev) => [...prev, { content: `Error killing Tedana process: ${error}`, isError: true }]);
     }
-  }, []);
+  }, [jobs]);
 
-  return { output, loading, executeTedanaCommand, killTedanaExecution };
+  return { output, loading, jobs, progress, executeTedanaCommand, killTedanaExecution };
 }
\ No newline at end of file
@@ -7,22 +7,100 @@ export interface BoldMetadata {
   slice_timing_corrected?: boolean;
   start_time?: number;
   task_name?: string;
+  sources: { [field: string]: string };
+}
+
+export interface NiftiHeader {
+  version: number;
+  dimensions: number[];
+  voxel_sizes: number[];
+  volumes: number;
+  repetition_time?: number;
+  datatype: string;
+  datatype_code: number;
+  qform_code: number;
+  sform_code: number;
+  affine: number[][];
+}
+
+// A row of participants.tsv, sessions.tsv or scans.tsv; n/a is null.
+export type Attributes = { [column: string]: string | number | null };
+
+export interface FunctionalRun {
+  key: string;
+  task?: string;
+  acq?: string;
+  ce?: string;
+  rec?: string;
+  dir?: string;
+  run?: number;
+  space?: string;
+  echo_nifti_file_paths: string[];
+  sidecar_file_paths: string[];
+  metadata: BoldMetadata[];
+  headers: (NiftiHeader | null)[];
+  warnings: string[];
+  mask?: string;
+  scans: Attributes[];
 }
 
 export interface Session {
   sub_id: number;
   name: string;
-  echo_nifti_file_paths: string[];
+  attributes: Attributes;
+  runs: FunctionalRun[];
 }
 
 export interface Subject {
   id: number;
   name: string;
+  attributes: Attributes;
   sessions: Session[];
 }
 
+export interface RunRef {
+  subject: string;
+  session: string;
+  run: string;
+}
+
+export interface SessionRef {
+  subject: string;
+  session: string;
+}
+
+// Everything inside an added or removed subject or session is listed too.
+export interface StructureDiff {
+  added_subjects: string[];
+  removed_subjects: string[];
+  added_sessions: SessionRef[];
+  removed_sessions: SessionRef[];
+  added_runs: RunRef[];
+  removed_runs: RunRef[];
+}
+
+export interface StructureChange {
+  watch_id: number;
+  path: string;
+  diff: StructureDiff;
+  structure: BidsStructure;
+}
+
+export interface Protocol {
+  echo_count: number;
+  echo_times: (number | null)[];
+  repetition_time?: number;
+  runs: RunRef[];
+}
+
+export interface DerivativeQuery {
+  pipeline: string;
+  space?: string;
+  desc?: string;
+}
+
 export interface BidsStructure {
-  metadata: BoldMetadata[];
+  protocols: Protocol[];
   subjects: Subject[];
 }
 
@@ -33,7 +111,7 @@ export interface TedanaConfig {
   mask: string;
   prefix: string;
   convention: "orig" | "bids" | null;
-  maskType: "dropout" | "decay" | "";
+  maskType: ("dropout" | "decay" | "none")[];
   fitType: "loglin" | "curvefit" | null;
   combMode: "t2s" | null;
   tedpca: string | null;
@@ -52,4 +130,163 @@ export interface TedanaConfig {
   t2smap: string | null;
   mix: string | null;
   overwrite: boolean;
-}
\ No newline at end of file
+}
+export type JobState = "queued" | "running" | "succeeded" | "failed" | "cancelled";
+
+export interface Job {
+  id: number;
+  subject: string;
+  session: string;
+  run: string;
+  python_path: string;
+  args?: TedanaConfig | null;
+  /** Deprecated shell string, only set for jobs queued without `args`. */
+  command_args?: string | null;
+  state: JobState;
+  message?: string;
+}
+
+export interface TedanaLine {
+  job_id: number;
+  line: string;
+}
+
+export interface ConfigIssue {
+  field: keyof TedanaConfig;
+  severity: "error" | "warning";
+  message: string;
+}
+
+/** What the user has to do about a `BidsError`; see `BidsErrorCategory`. */
+export type BidsErrorCategory =
+  | "wrong_folder"
+  | "permission"
+  | "missing"
+  | "malformed"
+  | "naming"
+  | "other";
+
+export type BidsError = {
+  category: BidsErrorCategory;
+  path: string;
+  subject?: string | null;
+  session?: string | null;
+} & (
+  | { kind: "not_a_directory" }
+  | {
+      kind: "io";
+      io_kind: "not_found" | "permission_denied" | "invalid_data" | "other";
+      message: string;
+    }
+  | { kind: "json"; line?: number | null; column?: number | null; message: string }
+  | { kind: "table" | "invalid_name" | "missing"; message: string }
+);
+
+export type ValidationCode =
+  | "unreadable_directory"
+  | "no_subjects"
+  | "invalid_subject_label"
+  | "invalid_session_label"
+  | "missing_func_directory"
+  | "invalid_filename"
+  | "no_matching_files"
+  | "missing_sidecar"
+  | "invalid_sidecar"
+  | "missing_dataset_description"
+  | "invalid_dataset_description"
+  | "derivative_dataset";
+
+export interface ValidationIssue {
+  code: ValidationCode;
+  severity: "error" | "warning";
+  message: string;
+  path: string;
+  subject?: string;
+  session?: string;
+  error?: BidsError;
+}
+
+export interface DatasetDescription {
+  name?: string;
+  bids_version?: string;
+  dataset_type: "raw" | "derivative";
+  generated_by: { name: string; version?: string }[];
+}
+
+export interface DerivativeDataset {
+  name: string;
+  path: string;
+  description?: DatasetDescription;
+}
+
+export interface ValidationReport {
+  issues: ValidationIssue[];
+  valid_subjects: string[];
+  invalid_subjects: string[];
+  dataset?: DatasetDescription;
+  derivatives: DerivativeDataset[];
+  ignore_patterns: string[];
+  ignored_paths: string[];
+}
+
+export type ReasonCode =
+  | "too_few_echoes"
+  | "missing_echo"
+  | "duplicate_echo"
+  | "echo_order"
+  | "unreadable_header"
+  | "matrix_mismatch"
+  | "volume_mismatch"
+  | "voxel_size_mismatch"
+  | "orientation_mismatch"
+  | "repetition_time_mismatch"
+  | "missing_echo_time"
+  | "echo_times_not_increasing";
+
+export interface ConsistencyIssue {
+  code: ReasonCode;
+  severity: "error" | "warning";
+  message: string;
+}
+
+export interface RunConsistency extends RunRef {
+  issues: ConsistencyIssue[];
+}
+
+export type EnvironmentKind = "conda" | "venv" | "uv" | "pyenv" | "homebrew" | "system";
+
+export interface DiscoveredEnvironment {
+  kind: EnvironmentKind;
+  interpreter: string;
+  prefix: string;
+  bin_dir: string;
+  python_version: string;
+  tedana_version: string;
+}
+
+export interface InstallProgress {
+  stage: "creating_environment" | "installing_packages" | "verifying" | "done";
+  line: string;
+  is_error: boolean;
+}
+
+export type TedanaStage =
+  | "starting"
+  | "loading_data"
+  | "adaptive_mask"
+  | "t2s_fit"
+  | "pca"
+  | "ica"
+  | "component_selection"
+  | "report"
+  | "done";
+
+export interface TedanaProgress {
+  job_id: number;
+  stage: TedanaStage;
+  percent: number;
+  ica_attempts: number;
+  ica_iterations?: number;
+  elapsed_secs: number;
+  eta_secs?: number;
+}
@@ -3,8 +3,9 @@ import ProjectDir from "../components/ProcessExecute/ProjectDir";
 import Config from "../components/ProcessExecute/Config";
 import RunScript from "../components/ProcessExecute/RunScript";
 import TedanaReport from "../components/ProcessExecute/TedanaReport";
-import { BidsStructure } from "../util/types";
+import { BidsStructure, RunRef } from "../util/types";
 import { useRunTedana } from "../util/hooks/useRunTedana";
+import { useBidsWatch } from "../util/hooks/useBidsWatch";
 
 function ProcessSetup() {
   const [activeStep, setActiveStep] = useState(0);
@@ -15,9 +16,22 @@ function ProcessSetup() {
   const [selectedSessions, setSelectedSessions] = useState<{
     [subjectId: string]: string[];
   }>({});
+  const [selectedRuns, setSelectedRuns] = useState<RunRef[] | null>(null);
 
-  const { output, loading, executeTedanaCommand, killTedanaExecution } =
-    useRunTedana();
+  const {
+    output,
+    loading,
+    jobs,
+    progress,
+    executeTedanaCommand,
+    killTedanaExecution,
+  } = useRunTedana();
+
+  // Subjects converted or outputs written while the app is open show up
+  // without reloading the directory.
+  useBidsWatch(directory, bidsStructure, (change) => {
+    setBidsStructure(change.structure);
+  });
 
   const steps = [
     "Working Directory",
@@ -45,6 +59,7 @@ function ProcessSetup() {
             directory={directory}
             setSelectedSubjects={setSelectedSubjects}
             setSelectedSessions={setSelectedSessions}
+            setSelectedRuns={setSelectedRuns}
           />
         );
       case 2:
@@ -52,8 +67,15 @@ function ProcessSetup() {
           <RunScript
             output={output}
             loading={loading}
+            jobs={jobs}
+            progress={progress}
             onExecute={() =>
-              executeTedanaCommand(selectedSubjects, selectedSessions)
+              executeTedanaCommand(
+                selectedSubjects,
+                selectedSessions,
+                bidsStructure,
+                selectedRuns
+              )
             }
             onKill={killTedanaExecution}
           />
@@ -90,7 +112,12 @@ function ProcessSetup() {
   const handleNext = async () => {
     if (activeStep === 1) {
       setActiveStep(activeStep + 1);
-      await executeTedanaCommand(selectedSubjects, selectedSessions);
+      await executeTedanaCommand(
+        selectedSubjects,
+        selectedSessions,
+        bidsStructure,
+        selectedRuns
+      );
     } else if (activeStep < steps.length - 1) {
       setActiveStep(activeStep + 1);
     }
@@ -1,6 +1,9 @@
 import { useEffect, useState } from "react";
 import { invoke } from "@tauri-apps/api/tauri";
 import { BidsStructure } from "../util/types";
+import { storedDerivativesQuery } from "../util/derivatives";
+import { describeBidsError } from "../util/bidsError";
+import { useBidsWatch } from "../util/hooks/useBidsWatch";
 import TedanaReport from "../components/ProcessExecute/TedanaReport";
 
 const ReportViewer = () => {
@@ -29,13 +32,16 @@ const ReportViewer = () => {
       setDirectory(workingDir);
 
       try {
-        const structure: BidsStructure = await invoke(
-          "extract_bids_structure",
-          {
-            path: workingDir,
-            convention: convention,
-          }
-        );
+        const derivatives = storedDerivativesQuery();
+        const structure: BidsStructure = derivatives
+          ? await invoke("extract_derivatives_structure", {
+              path: workingDir,
+              derivatives,
+            })
+          : await invoke("extract_bids_structure", {
+              path: workingDir,
+              convention: convention,
+            });
 
         setBidsStructure(structure);
         setSelectedSubjects(structure.subjects.map((sub) => sub.name));
@@ -45,7 +51,7 @@ const ReportViewer = () => {
         });
         setSelectedSessions(sessions);
       } catch (err) {
-        setError(`Error loading BIDS structure: ${err}`);
+        setError(`Error loading BIDS structure: ${describeBidsError(err)}`);
       }
       setLoading(false);
     };
@@ -53,6 +59,28 @@ const ReportViewer = () => {
     initializeViewer();
   }, []);
 
+  // New subjects and sessions are shown as they appear; removed ones are
+  // dropped from the selection.
+  useBidsWatch(directory, bidsStructure, ({ diff, structure }) => {
+    setBidsStructure(structure);
+    setSelectedSubjects((prev) => [
+      ...prev.filter((sub) => !diff.removed_subjects.includes(sub)),
+      ...diff.added_subjects,
+    ]);
+    setSelectedSessions((prev) => {
+      const sessions = { ...prev };
+      diff.removed_sessions.forEach(({ subject, session }) => {
+        sessions[subject] = (sessions[subject] ?? []).filter(
+          (ses) => ses !== session
+        );
+      });
+      diff.added_sessions.forEach(({ subject, session }) => {
+        sessions[subject] = [...(sessions[subject] ?? []), session];
+      });
+      return sessions;
+    });
+  });
+
   if (loading) {
     return (
       <div className="flex justify-center items-center h-full">